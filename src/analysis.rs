@@ -0,0 +1,316 @@
+//! Background analysis of every photo in the folder, to flag soft and
+//! badly exposed frames without opening each one and to find duplicates.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::duplicates;
+use crate::photo_pair::PhotoPair;
+use crate::viewer::{self, DecodedImage};
+
+/// Longest side of the images analysed. Scores depend on the scale, so every
+/// photo is measured at the same size.
+const ANALYSIS_SIZE: u32 = 1500;
+/// Analysis threads. Kept low so analysis doesn't hold up decoding for
+/// display.
+const ANALYSIS_THREADS: usize = 2;
+/// Side of the square measured around the focus point, as a fraction of the
+/// image's shorter side.
+const FOCUS_WINDOW: f32 = 0.2;
+
+/// Channel value from which a pixel counts as a clipped highlight.
+const HIGHLIGHT_LEVEL: u8 = 250;
+/// Channel value up to which a pixel counts as a clipped shadow, when all
+/// three channels are this dark.
+const SHADOW_LEVEL: u8 = 5;
+/// Mean luma below which a photo is underexposed whatever its clipping.
+const UNDEREXPOSED_MEAN: f32 = 0.12;
+
+/// Sharpness below which a photo is flagged as soft by default.
+pub const DEFAULT_SOFT_THRESHOLD: f32 = 100.0;
+/// Fraction of clipped pixels above which a photo is flagged by default.
+pub const DEFAULT_CLIPPING_THRESHOLD: f32 = 0.05;
+
+/// Scores for one photo.
+#[derive(Debug, Clone, Copy)]
+pub struct Analysis {
+    /// Variance of the Laplacian of the luma; higher is sharper.
+    pub sharpness: f32,
+    /// True if `sharpness` was measured around the camera's focus point
+    /// rather than over the whole frame.
+    pub at_focus_point: bool,
+    /// Fraction of pixels with a channel at or near white.
+    pub clipped_highlights: f32,
+    /// Fraction of pixels at or near black.
+    pub clipped_shadows: f32,
+    /// Mean luma from 0.0 (black) to 1.0 (white).
+    pub mean_luma: f32,
+    /// Perceptual hash for finding duplicates.
+    pub hash: u64,
+}
+
+impl Analysis {
+    pub fn is_soft(&self, threshold: f32) -> bool {
+        self.sharpness < threshold
+    }
+
+    /// More than `threshold` of the frame is blown out.
+    pub fn is_overexposed(&self, threshold: f32) -> bool {
+        self.clipped_highlights > threshold
+    }
+
+    /// More than `threshold` of the frame is crushed to black, or the frame
+    /// is dark overall.
+    pub fn is_underexposed(&self, threshold: f32) -> bool {
+        self.clipped_shadows > threshold || self.mean_luma < UNDEREXPOSED_MEAN
+    }
+}
+
+/// Whether a pixel is blown out or crushed to black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clipping {
+    None,
+    Highlight,
+    Shadow,
+}
+
+/// Clipping of an RGB(A) pixel. A pixel is a clipped highlight when any
+/// channel is near white, since that channel has lost detail, but a
+/// clipped shadow only when all of them are near black.
+pub fn clipping(pixel: &[u8]) -> Clipping {
+    let rgb = &pixel[..3];
+    if rgb.iter().any(|&c| c >= HIGHLIGHT_LEVEL) {
+        Clipping::Highlight
+    } else if rgb.iter().all(|&c| c <= SHADOW_LEVEL) {
+        Clipping::Shadow
+    } else {
+        Clipping::None
+    }
+}
+
+/// Rec. 601 luma of an RGB(A) pixel, from 0.0 to 255.0.
+pub fn luma(pixel: &[u8]) -> f32 {
+    0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32
+}
+
+struct AnalysisRequest {
+    path: PathBuf,
+    /// Focus point as a fraction of the upright image's size.
+    focus_point: Option<(f32, f32)>,
+}
+
+/// Photos waiting for an analysis thread, in folder order.
+#[derive(Default)]
+struct AnalysisQueue {
+    pending: VecDeque<AnalysisRequest>,
+    /// Bumped by `Analyzer::clear`; results from older generations are stale.
+    generation: u64,
+    shutdown: bool,
+}
+
+type SharedQueue = Arc<(Mutex<AnalysisQueue>, Condvar)>;
+type AnalysisResult = (u64, PathBuf, Option<Analysis>);
+
+/// Analyses photos on background threads and keeps the scores by preview
+/// path, so they survive a rescan of the same folder.
+pub struct Analyzer {
+    queue: SharedQueue,
+    receiver: Receiver<AnalysisResult>,
+    results: HashMap<PathBuf, Analysis>,
+    /// Paths queued or being analysed.
+    pending: HashSet<PathBuf>,
+    failed: HashSet<PathBuf>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Analyzer {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.queue;
+        lock.lock().unwrap().shutdown = true;
+        condvar.notify_all();
+    }
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        let queue: SharedQueue = Arc::new((Mutex::new(AnalysisQueue::default()), Condvar::new()));
+        let (result_sender, result_receiver) = channel::<AnalysisResult>();
+
+        for _ in 0..ANALYSIS_THREADS {
+            let queue = Arc::clone(&queue);
+            let result_sender = result_sender.clone();
+            thread::spawn(move || analysis_worker(queue, result_sender));
+        }
+
+        Self {
+            queue,
+            receiver: result_receiver,
+            results: HashMap::new(),
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    /// Queues every pair that hasn't been analysed yet.
+    pub fn analyze(&mut self, pairs: &[PhotoPair]) {
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        for pair in pairs {
+            let path = pair.display_path();
+            if self.results.contains_key(path)
+                || self.pending.contains(path)
+                || self.failed.contains(path)
+            {
+                continue;
+            }
+            self.pending.insert(path.to_path_buf());
+            queue.pending.push_back(AnalysisRequest {
+                path: path.to_path_buf(),
+                focus_point: pair.exif.as_ref().and_then(|e| e.upright_focus_point()),
+            });
+        }
+        condvar.notify_all();
+    }
+
+    /// Collects finished analyses.
+    pub fn poll(&mut self) {
+        let generation = self.queue.0.lock().unwrap().generation;
+        while let Ok((result_generation, path, analysis)) = self.receiver.try_recv() {
+            if result_generation != generation {
+                continue;
+            }
+            self.pending.remove(&path);
+            match analysis {
+                Some(analysis) => {
+                    self.results.insert(path, analysis);
+                }
+                None => {
+                    self.failed.insert(path);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Analysis> {
+        self.results.get(path)
+    }
+
+    /// Photos still waiting to be analysed.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn clear(&mut self) {
+        let mut queue = self.queue.0.lock().unwrap();
+        queue.pending.clear();
+        queue.generation += 1;
+        drop(queue);
+
+        self.results.clear();
+        self.pending.clear();
+        self.failed.clear();
+    }
+}
+
+fn analysis_worker(queue: SharedQueue, results: Sender<AnalysisResult>) {
+    let (lock, condvar) = &*queue;
+    loop {
+        let (generation, request) = {
+            let mut queue = lock.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(request) = queue.pending.pop_front() {
+                    break (queue.generation, request);
+                }
+                queue = condvar.wait(queue).unwrap();
+            }
+        };
+
+        let analysis = viewer::decode_image(&request.path, Some(ANALYSIS_SIZE))
+            .map(|image| analyze_image(&image, request.focus_point));
+        if analysis.is_none() {
+            eprintln!("Failed to analyse: {}", request.path.display());
+        }
+        if results.send((generation, request.path, analysis)).is_err() {
+            return;
+        }
+    }
+}
+
+fn analyze_image(image: &DecodedImage, focus_point: Option<(f32, f32)>) -> Analysis {
+    let mut luma_plane = Vec::with_capacity(image.width * image.height);
+    let mut highlights = 0usize;
+    let mut shadows = 0usize;
+    for pixel in image.pixels.chunks_exact(4) {
+        match clipping(pixel) {
+            Clipping::Highlight => highlights += 1,
+            Clipping::Shadow => shadows += 1,
+            Clipping::None => {}
+        }
+        luma_plane.push(luma(pixel));
+    }
+    let pixels = luma_plane.len().max(1) as f32;
+
+    let focus_region = focus_point.and_then(|point| focus_window(image, point));
+    let region = focus_region.unwrap_or((0, 0, image.width, image.height));
+    Analysis {
+        sharpness: laplacian_variance(&luma_plane, image.width, region),
+        at_focus_point: focus_region.is_some(),
+        clipped_highlights: highlights as f32 / pixels,
+        clipped_shadows: shadows as f32 / pixels,
+        mean_luma: luma_plane.iter().sum::<f32>() / pixels / 255.0,
+        hash: duplicates::perceptual_hash(&luma_plane, image.width, image.height),
+    }
+}
+
+/// The square around `point` that sharpness is measured in, as (left, top,
+/// right, bottom) pixels, or `None` if it would be too small to measure.
+fn focus_window(image: &DecodedImage, (x, y): (f32, f32)) -> Option<(usize, usize, usize, usize)> {
+    let half = image.width.min(image.height) as f32 * FOCUS_WINDOW / 2.0;
+    let center_x = x.clamp(0.0, 1.0) * image.width as f32;
+    let center_y = y.clamp(0.0, 1.0) * image.height as f32;
+    let left = (center_x - half).max(0.0) as usize;
+    let top = (center_y - half).max(0.0) as usize;
+    let right = ((center_x + half) as usize).min(image.width);
+    let bottom = ((center_y + half) as usize).min(image.height);
+    (right >= left + 3 && bottom >= top + 3).then_some((left, top, right, bottom))
+}
+
+/// Variance of the 4-neighbour Laplacian over `region` of a `width` wide
+/// luma image. Blur removes the high frequencies the Laplacian responds to,
+/// so a soft image scores low.
+fn laplacian_variance(
+    luma: &[f32],
+    width: usize,
+    (left, top, right, bottom): (usize, usize, usize, usize),
+) -> f32 {
+    let mut sum = 0.0f64;
+    let mut sum_squares = 0.0f64;
+    let mut count = 0usize;
+    for y in (top + 1)..bottom.saturating_sub(1) {
+        let row = y * width;
+        for x in (left + 1)..right.saturating_sub(1) {
+            let i = row + x;
+            let laplacian =
+                4.0 * luma[i] - luma[i - 1] - luma[i + 1] - luma[i - width] - luma[i + width];
+            sum += laplacian as f64;
+            sum_squares += (laplacian * laplacian) as f64;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    let mean = sum / count as f64;
+    (sum_squares / count as f64 - mean * mean) as f32
+}
@@ -0,0 +1,140 @@
+//! Groups consecutive frames of a continuous shooting burst.
+
+use crate::photo_pair::PhotoPair;
+
+/// Longest gap between two frames of the same burst, in seconds. Even the
+/// slowest continuous mode shoots a few frames a second.
+const MAX_FRAME_GAP: f64 = 1.0;
+
+/// Assigns a burst to every run of two or more consecutive pairs shot in
+/// quick succession. Pairs must already be sorted by folder and name, which
+/// is shooting order. Frames are linked when the camera numbered them as a
+/// sequence, or failing that when they were taken within `MAX_FRAME_GAP`.
+pub fn group_bursts(pairs: &mut [PhotoPair]) {
+    let mut next_burst = 0;
+    let mut start = 0;
+    for idx in 1..=pairs.len() {
+        if idx < pairs.len() && same_burst(&pairs[idx - 1], &pairs[idx]) {
+            continue;
+        }
+        if idx - start >= 2 {
+            for pair in &mut pairs[start..idx] {
+                pair.burst = Some(next_burst);
+            }
+            next_burst += 1;
+        }
+        start = idx;
+    }
+}
+
+fn same_burst(previous: &PhotoPair, pair: &PhotoPair) -> bool {
+    if previous.relative_dir != pair.relative_dir {
+        return false;
+    }
+    let (Some(previous), Some(exif)) = (&previous.exif, &pair.exif) else {
+        return false;
+    };
+
+    if let (Some(previous), Some(sequence)) = (previous.sequence_number, exif.sequence_number) {
+        return sequence == previous + 1;
+    }
+    match (previous.capture_time(), exif.capture_time()) {
+        (Some(previous), Some(time)) => (0.0..=MAX_FRAME_GAP).contains(&(time - previous)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::exif::ExifData;
+
+    fn pair(dir: &str, name: &str, exif: ExifData) -> PhotoPair {
+        let path = PathBuf::from(dir).join(format!("{}.JPG", name));
+        let mut pair = PhotoPair::new(PathBuf::from(dir), name.to_string(), Some(path), None);
+        pair.exif = Some(exif);
+        pair
+    }
+
+    fn numbered(sequence: u32) -> ExifData {
+        ExifData {
+            sequence_number: Some(sequence),
+            ..Default::default()
+        }
+    }
+
+    fn taken_at(time: &str, sub_sec: &str) -> ExifData {
+        ExifData {
+            date_time_original: Some(time.to_string()),
+            sub_sec_time_original: Some(sub_sec.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn bursts(pairs: &[PhotoPair]) -> Vec<Option<u32>> {
+        pairs.iter().map(|p| p.burst).collect()
+    }
+
+    #[test]
+    fn groups_sequence_runs() {
+        let mut pairs = vec![
+            pair("", "DSCF0001", numbered(1)),
+            pair("", "DSCF0002", numbered(2)),
+            pair("", "DSCF0003", numbered(3)),
+            // A new burst starts over at 1
+            pair("", "DSCF0004", numbered(1)),
+            pair("", "DSCF0005", numbered(2)),
+            pair("", "DSCF0006", numbered(5)),
+        ];
+        group_bursts(&mut pairs);
+        assert_eq!(
+            bursts(&pairs),
+            [Some(0), Some(0), Some(0), Some(1), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_win_over_capture_time() {
+        let same_second = |sequence| ExifData {
+            sequence_number: Some(sequence),
+            ..taken_at("2024:05:01 10:00:00", "")
+        };
+        let mut pairs = vec![
+            pair("", "DSCF0001", same_second(1)),
+            pair("", "DSCF0002", same_second(1)),
+        ];
+        group_bursts(&mut pairs);
+        assert_eq!(bursts(&pairs), [None, None]);
+    }
+
+    #[test]
+    fn falls_back_to_capture_time() {
+        let mut pairs = vec![
+            pair("", "DSCF0001", taken_at("2024:05:01 10:00:00", "10")),
+            pair("", "DSCF0002", taken_at("2024:05:01 10:00:00", "60")),
+            pair("", "DSCF0003", taken_at("2024:05:01 10:00:01", "20")),
+            pair("", "DSCF0004", taken_at("2024:05:01 10:00:03", "00")),
+            pair("", "DSCF0005", ExifData::default()),
+            pair("", "DSCF0006", taken_at("2024:05:01 10:00:03", "50")),
+        ];
+        group_bursts(&mut pairs);
+        assert_eq!(
+            bursts(&pairs),
+            [Some(0), Some(0), Some(0), None, None, None]
+        );
+    }
+
+    #[test]
+    fn stops_at_folder_boundaries() {
+        let mut pairs = vec![
+            pair("100_FUJI", "DSCF0001", numbered(1)),
+            pair("100_FUJI", "DSCF0002", numbered(2)),
+            pair("101_FUJI", "DSCF0003", numbered(3)),
+            pair("101_FUJI", "DSCF0004", numbered(4)),
+        ];
+        group_bursts(&mut pairs);
+        assert_eq!(bursts(&pairs), [Some(0), Some(0), Some(1), Some(1)]);
+    }
+}
@@ -1,7 +1,64 @@
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use crate::photo_pair::{DeletionAction, PhotoPair};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::{self, DeletionBatch, JournalEntry};
+use crate::photo_pair::PhotoPair;
+use crate::xmp;
+
+/// Name of the staging folder created next to the source files.
+pub const CULLED_DIR_NAME: &str = "_culled";
+
+/// Where marked files go when deletions are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeletionMode {
+    /// Move into the freedesktop.org Trash, restorable from the file manager.
+    Trash,
+    /// Move into a `_culled` folder next to the source file.
+    CulledFolder,
+    /// Unlink the file. Cannot be recovered.
+    Permanent,
+}
+
+impl Default for DeletionMode {
+    fn default() -> Self {
+        if cfg!(all(unix, not(target_os = "macos"))) {
+            DeletionMode::Trash
+        } else {
+            DeletionMode::CulledFolder
+        }
+    }
+}
+
+impl DeletionMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DeletionMode::Trash => "Move to Trash",
+            DeletionMode::CulledFolder => "Move to _culled folder",
+            DeletionMode::Permanent => "Delete permanently",
+        }
+    }
+
+    /// Modes offered in the confirmation dialog on this platform.
+    pub fn available() -> &'static [DeletionMode] {
+        if cfg!(all(unix, not(target_os = "macos"))) {
+            &[DeletionMode::Trash, DeletionMode::CulledFolder, DeletionMode::Permanent]
+        } else {
+            &[DeletionMode::CulledFolder, DeletionMode::Permanent]
+        }
+    }
+
+    /// Moves or removes `path`, returning where it ended up (if anywhere).
+    fn remove(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        match self {
+            DeletionMode::Trash => trash::move_to_trash(path).map(Some),
+            DeletionMode::CulledFolder => move_to_culled_folder(path).map(Some),
+            DeletionMode::Permanent => fs::remove_file(path).map(|_| None),
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct DeletionSummary {
@@ -38,79 +95,263 @@ pub fn calculate_deletion_summary(pairs: &[PhotoPair]) -> DeletionSummary {
     let mut summary = DeletionSummary::default();
 
     for pair in pairs {
-        match pair.action {
-            DeletionAction::KeepBoth => {}
-            DeletionAction::DeleteRaw => {
-                if let Some(ref raw_path) = pair.raw_path {
-                    summary.raw_count += 1;
-                    summary.raw_bytes += file_size(raw_path);
-                }
-            }
-            DeletionAction::DeleteJpeg => {
-                summary.jpeg_count += 1;
-                summary.jpeg_bytes += file_size(&pair.jpeg_path);
-            }
-            DeletionAction::DeleteBoth => {
-                summary.jpeg_count += 1;
-                summary.jpeg_bytes += file_size(&pair.jpeg_path);
-                if let Some(ref raw_path) = pair.raw_path {
-                    summary.raw_count += 1;
-                    summary.raw_bytes += file_size(raw_path);
-                }
-            }
+        let (jpeg, raw) = pair.marked_files();
+        if let Some(jpeg_path) = jpeg {
+            summary.jpeg_count += 1;
+            summary.jpeg_bytes += file_size(jpeg_path);
+        }
+        if let Some(raw_path) = raw {
+            summary.raw_count += 1;
+            summary.raw_bytes += file_size(raw_path);
         }
     }
 
     summary
 }
 
-fn file_size(path: &PathBuf) -> u64 {
+fn file_size(path: &Path) -> u64 {
     fs::metadata(path).map(|m| m.len()).unwrap_or(0)
 }
 
-pub fn execute_deletions(pairs: &[PhotoPair]) -> Result<usize, Vec<String>> {
-    let mut deleted = 0;
+/// Removes every marked file using `mode` and records the batch in the
+/// journal kept in `journal_dir` so it can be undone later.
+pub fn execute_deletions(
+    pairs: &[PhotoPair],
+    mode: DeletionMode,
+    journal_dir: &Path,
+) -> Result<usize, Vec<String>> {
+    let mut batch = DeletionBatch::new(mode);
     let mut errors = Vec::new();
 
+    let mut deleted = 0;
+
     for pair in pairs {
-        match pair.action {
-            DeletionAction::KeepBoth => {}
-            DeletionAction::DeleteRaw => {
-                if let Some(ref raw_path) = pair.raw_path {
-                    if let Err(e) = fs::remove_file(raw_path) {
-                        errors.push(format!("{}: {}", raw_path.display(), e));
-                    } else {
+        let (jpeg, raw) = pair.marked_files();
+        let removes_all = jpeg.is_some() == pair.has_jpeg() && raw.is_some() == pair.has_raw();
+        // The XMP sidecar goes along once nothing is left for it to describe
+        let sidecar = Some(xmp::sidecar_path(pair)).filter(|p| removes_all && p.exists());
+
+        for (path, is_sidecar) in jpeg
+            .into_iter()
+            .chain(raw)
+            .map(|p| (p, false))
+            .chain(sidecar.as_deref().map(|p| (p, true)))
+        {
+            match mode.remove(path) {
+                Ok(staged) => {
+                    batch.entries.push(JournalEntry {
+                        original: path.to_path_buf(),
+                        staged,
+                        action: pair.action,
+                    });
+                    if !is_sidecar {
                         deleted += 1;
                     }
                 }
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
             }
-            DeletionAction::DeleteJpeg => {
-                if let Err(e) = fs::remove_file(&pair.jpeg_path) {
-                    errors.push(format!("{}: {}", pair.jpeg_path.display(), e));
-                } else {
-                    deleted += 1;
-                }
-            }
-            DeletionAction::DeleteBoth => {
-                if let Err(e) = fs::remove_file(&pair.jpeg_path) {
-                    errors.push(format!("{}: {}", pair.jpeg_path.display(), e));
-                } else {
-                    deleted += 1;
-                }
-                if let Some(ref raw_path) = pair.raw_path {
-                    if let Err(e) = fs::remove_file(raw_path) {
-                        errors.push(format!("{}: {}", raw_path.display(), e));
-                    } else {
-                        deleted += 1;
+        }
+    }
+
+    if !batch.entries.is_empty()
+        && let Err(e) = journal::append_batch(journal_dir, &batch)
+    {
+        errors.push(format!("Failed to write deletion journal: {}", e));
+    }
+
+    if errors.is_empty() {
+        Ok(deleted)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Moves every file of the most recent journaled batch back to where it came
+/// from, returning 0 if there is nothing to undo. Permanently deleted files
+/// are reported as errors. Files that could not be restored stay in the
+/// journal so the undo can be retried.
+pub fn undo_last_deletion(journal_dir: &Path) -> Result<usize, Vec<String>> {
+    let batch = match journal::last_batch(journal_dir) {
+        Ok(Some(batch)) => batch,
+        Ok(None) => return Ok(0),
+        Err(e) => return Err(vec![format!("Failed to read deletion journal: {}", e)]),
+    };
+
+    let mut restored = 0;
+    let mut errors = Vec::new();
+    let mut remaining = Vec::new();
+
+    for entry in &batch.entries {
+        let Some(ref staged) = entry.staged else {
+            errors.push(format!("{}: deleted permanently", entry.original.display()));
+            continue;
+        };
+        if entry.original.exists() {
+            errors.push(format!("{}: already exists", entry.original.display()));
+            remaining.push(entry.clone());
+            continue;
+        }
+        match move_file(staged, &entry.original) {
+            Ok(()) => {
+                restored += 1;
+                match batch.mode {
+                    DeletionMode::Trash => trash::remove_info(staged),
+                    DeletionMode::CulledFolder => {
+                        // Only succeeds once the folder is empty
+                        if let Some(dir) = staged.parent() {
+                            let _ = fs::remove_dir(dir);
+                        }
                     }
+                    DeletionMode::Permanent => {}
                 }
             }
+            Err(e) => {
+                errors.push(format!("{}: {}", staged.display(), e));
+                remaining.push(entry.clone());
+            }
         }
     }
 
+    if let Err(e) = journal::retain_last_batch(journal_dir, remaining) {
+        errors.push(format!("Failed to update deletion journal: {}", e));
+    }
+
     if errors.is_empty() {
-        Ok(deleted)
+        Ok(restored)
     } else {
         Err(errors)
     }
 }
+
+fn move_to_culled_folder(path: &Path) -> io::Result<PathBuf> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let culled_dir = parent.join(CULLED_DIR_NAME);
+    fs::create_dir_all(&culled_dir)?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let target = unique_path(&culled_dir, &file_name.to_string_lossy());
+    move_file(path, &target)?;
+    Ok(target)
+}
+
+/// Returns `dir/name`, or `dir/stem (n).ext` if that is already taken.
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if !candidate.exists() {
+        return candidate;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) => (stem, format!(".{}", ext)),
+        None => (name, String::new()),
+    };
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|p| !p.exists())
+        .expect("unbounded range")
+}
+
+/// Renames `from` to `to`, copying across filesystems when a rename isn't possible.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Minimal implementation of the freedesktop.org Trash specification
+/// (home trash only; files on other filesystems are copied there).
+mod trash {
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+
+    pub fn move_to_trash(path: &Path) -> io::Result<PathBuf> {
+        let path = fs::canonicalize(path)?;
+        let trash_dir = home_trash_dir()?;
+        let files_dir = trash_dir.join("files");
+        let info_dir = trash_dir.join("info");
+        fs::create_dir_all(&files_dir)?;
+        fs::create_dir_all(&info_dir)?;
+
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        let (stem, ext) = match file_name.rsplit_once('.') {
+            Some((stem, ext)) => (stem.to_string(), format!(".{}", ext)),
+            None => (file_name.clone(), String::new()),
+        };
+
+        // The spec reserves a name by creating its .trashinfo with O_EXCL first
+        for n in 0.. {
+            let name = if n == 0 {
+                file_name.clone()
+            } else {
+                format!("{} ({}){}", stem, n, ext)
+            };
+            let info_path = info_dir.join(format!("{}.trashinfo", name));
+            let mut info = match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                Ok(f) => f,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            let target = files_dir.join(&name);
+            if target.exists() {
+                drop(info);
+                let _ = fs::remove_file(&info_path);
+                continue;
+            }
+
+            let result = write!(
+                info,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode_path(&path),
+                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+            )
+            .and_then(|_| super::move_file(&path, &target));
+            if let Err(e) = result {
+                let _ = fs::remove_file(&info_path);
+                return Err(e);
+            }
+            return Ok(target);
+        }
+        unreachable!()
+    }
+
+    /// Deletes the .trashinfo belonging to a file that was moved back out of the trash.
+    pub fn remove_info(trashed: &Path) {
+        let (Some(files_dir), Some(name)) = (trashed.parent(), trashed.file_name()) else {
+            return;
+        };
+        if let Some(trash_dir) = files_dir.parent() {
+            let info_name = format!("{}.trashinfo", name.to_string_lossy());
+            let _ = fs::remove_file(trash_dir.join("info").join(info_name));
+        }
+    }
+
+    fn home_trash_dir() -> io::Result<PathBuf> {
+        if let Some(data_home) = std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+            return Ok(PathBuf::from(data_home).join("Trash"));
+        }
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local/share/Trash"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))
+    }
+
+    /// Percent-encodes an absolute path as required for the `Path=` key.
+    fn encode_path(path: &Path) -> String {
+        let mut encoded = String::new();
+        for &b in path.to_string_lossy().as_bytes() {
+            if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+                encoded.push(b as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+        encoded
+    }
+}
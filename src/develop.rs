@@ -0,0 +1,89 @@
+//! Develops RAW sensor data on the CPU: demosaic, white balance and a tone
+//! curve. This is a quick look at what the sensor captured, not a RAW
+//! converter; there is no colour matrix, noise reduction or sharpening.
+
+use image::{DynamicImage, RgbImage};
+
+use crate::raf::CfaImage;
+
+/// Entries in the tone curve lookup table over linear 0.0..=1.0.
+const CURVE_STEPS: usize = 4096;
+/// How much of the S-curve is blended into the plain sRGB encoding.
+const CURVE_CONTRAST: f32 = 0.25;
+
+/// Develops the visible area of the sensor at full resolution. Every pixel
+/// keeps its own colour and interpolates the other two from the samples of
+/// that colour around it: its 3x3 neighbourhood, which is bilinear
+/// interpolation for Bayer, widened to 5x5 where an X-Trans neighbourhood
+/// lacks a colour.
+pub fn develop(cfa: &CfaImage) -> Option<DynamicImage> {
+    let (top, left, height, width) = cfa.crop;
+    if width == 0 || height == 0 || cfa.samples.len() < cfa.width * cfa.height {
+        return None;
+    }
+
+    let range = (cfa.white_level - cfa.black_level).max(1.0);
+    // Scale so the weakest channel has a multiplier of 1 and clipped
+    // highlights stay neutral instead of turning pink or cyan
+    let min_multiplier = cfa.wb_multipliers.iter().cloned().fold(f32::MAX, f32::min);
+    let gains = cfa.wb_multipliers.map(|m| m / min_multiplier.max(f32::EPSILON) / range);
+    let curve = tone_curve();
+    let sample =
+        |r: usize, c: usize| (cfa.samples[r * cfa.width + c] as f32 - cfa.black_level).max(0.0);
+    let (bottom, right) = (top + height - 1, left + width - 1);
+
+    let mut pixels = vec![0u8; width * height * 3];
+    for (y, row) in pixels.chunks_exact_mut(width * 3).enumerate() {
+        let r = top + y;
+        for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+            let c = left + x;
+            let own = cfa.pattern.color_at(r, c) as usize;
+            let mut sums = [0.0f32; 3];
+            let mut counts = [0u32; 3];
+            for radius in 1..=2 {
+                sums = [0.0; 3];
+                counts = [0; 3];
+                for nr in r.saturating_sub(radius).max(top)..=(r + radius).min(bottom) {
+                    for nc in c.saturating_sub(radius).max(left)..=(c + radius).min(right) {
+                        let color = cfa.pattern.color_at(nr, nc) as usize;
+                        if color != own {
+                            sums[color] += sample(nr, nc);
+                            counts[color] += 1;
+                        }
+                    }
+                }
+                if (0..3).all(|color| color == own || counts[color] > 0) {
+                    break;
+                }
+            }
+            sums[own] = sample(r, c);
+            counts[own] = 1;
+
+            for channel in 0..3 {
+                let mean = sums[channel] / counts[channel].max(1) as f32;
+                let linear = (mean * gains[channel]).clamp(0.0, 1.0);
+                pixel[channel] = curve[(linear * (CURVE_STEPS - 1) as f32) as usize];
+            }
+        }
+    }
+
+    RgbImage::from_raw(width as u32, height as u32, pixels).map(DynamicImage::ImageRgb8)
+}
+
+/// Lookup table from linear light to display values: the sRGB transfer
+/// function with a gentle S-curve for some of the camera JPEG's contrast.
+fn tone_curve() -> Vec<u8> {
+    (0..CURVE_STEPS)
+        .map(|i| {
+            let linear = i as f32 / (CURVE_STEPS - 1) as f32;
+            let encoded = if linear <= 0.003_130_8 {
+                linear * 12.92
+            } else {
+                1.055 * linear.powf(1.0 / 2.4) - 0.055
+            };
+            let s_curve = encoded * encoded * (3.0 - 2.0 * encoded);
+            let value = encoded + (s_curve - encoded) * CURVE_CONTRAST;
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
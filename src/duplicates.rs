@@ -0,0 +1,196 @@
+//! Finds photos that appear more than once in a folder, e.g. after copying
+//! the same card twice or importing under different names.
+
+use crate::photo_pair::PhotoPair;
+
+/// Side of the grid a hash is computed from. Each row compares `HASH_SIZE`
+/// neighbouring cells, so the grid is one cell wider than it is tall.
+const HASH_SIZE: usize = 8;
+/// Most bits two hashes may differ by for their photos to be duplicates.
+/// Re-encoded or resized copies stay within a few bits; different frames
+/// of the same scene usually differ by more.
+const MAX_DISTANCE: u32 = 6;
+
+/// Photos that look the same, and the one to keep.
+pub struct DuplicateGroup {
+    /// Pair indices in folder order.
+    pub members: Vec<usize>,
+    pub keeper: usize,
+}
+
+/// Difference hash of a `width` wide luma image: the image is averaged down
+/// to a 9x8 grid and each bit records whether a cell is darker than its
+/// right neighbour. Survives rescaling, recompression and small exposure
+/// changes.
+pub fn perceptual_hash(luma: &[f32], width: usize, height: usize) -> u64 {
+    let columns = HASH_SIZE + 1;
+    let mut sums = [[0.0f32; HASH_SIZE + 1]; HASH_SIZE];
+    let mut counts = [[0u32; HASH_SIZE + 1]; HASH_SIZE];
+    for (y, row) in luma.chunks_exact(width.max(1)).enumerate().take(height) {
+        let cell_y = y * HASH_SIZE / height;
+        for (x, &value) in row.iter().enumerate() {
+            let cell_x = x * columns / width;
+            sums[cell_y][cell_x] += value;
+            counts[cell_y][cell_x] += 1;
+        }
+    }
+
+    let mut hash = 0u64;
+    for (row_sums, row_counts) in sums.iter().zip(&counts) {
+        let mean = |x: usize| row_sums[x] / row_counts[x].max(1) as f32;
+        for x in 0..HASH_SIZE {
+            hash = (hash << 1) | (mean(x) < mean(x + 1)) as u64;
+        }
+    }
+    hash
+}
+
+/// Groups pairs whose hashes are all within `MAX_DISTANCE` bits of each
+/// other. Every member is compared with the whole group, so a slow pan
+/// doesn't chain its frames into one group. `photos` holds the pair index,
+/// hash and sharpness of every pair that has been analysed. Each group keeps
+/// its highest rated member, then the sharpest, then complete RAW+JPEG
+/// pairs and then the first in the folder.
+pub fn find_duplicates(pairs: &[PhotoPair], photos: &[(usize, u64, f32)]) -> Vec<DuplicateGroup> {
+    // Positions in `photos`, joining the first group that takes them
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, &(_, hash, _)) in photos.iter().enumerate() {
+        let matches = |&member: &usize| (photos[member].1 ^ hash).count_ones() <= MAX_DISTANCE;
+        match groups.iter_mut().find(|group| group.iter().all(matches)) {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+
+    let complete = |pair: &PhotoPair| pair.has_raw() && pair.has_jpeg();
+    let mut groups: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .filter(|members| members.len() >= 2)
+        .map(|members| {
+            let keeper = members
+                .iter()
+                .map(|&i| photos[i])
+                .max_by(|&(a, _, a_sharpness), &(b, _, b_sharpness)| {
+                    let (a_pair, b_pair) = (&pairs[a], &pairs[b]);
+                    a_pair
+                        .rating
+                        .cmp(&b_pair.rating)
+                        .then(a_sharpness.total_cmp(&b_sharpness))
+                        .then(complete(a_pair).cmp(&complete(b_pair)))
+                        .then(b.cmp(&a))
+                })
+                .unwrap()
+                .0;
+            let mut members: Vec<usize> = members.iter().map(|&i| photos[i].0).collect();
+            members.sort_unstable();
+            DuplicateGroup { members, keeper }
+        })
+        .collect();
+    groups.sort_by_key(|group| group.members[0]);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn pair(name: &str, rating: u8, with_raw: bool) -> PhotoPair {
+        let raw = with_raw.then(|| PathBuf::from(format!("{}.RAF", name)));
+        let jpeg = Some(PathBuf::from(format!("{}.JPG", name)));
+        let mut pair = PhotoPair::new(PathBuf::new(), name.to_string(), jpeg, raw);
+        pair.rating = rating;
+        pair
+    }
+
+    /// A horizontal gradient with a bright square, `scale` times 16x12.
+    fn image(scale: usize) -> (Vec<f32>, usize, usize) {
+        let (width, height) = (16 * scale, 12 * scale);
+        let luma = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width / scale, i / width / scale);
+                if (4..8).contains(&x) && (3..7).contains(&y) {
+                    1.0
+                } else {
+                    x as f32 / 16.0 * 0.5
+                }
+            })
+            .collect();
+        (luma, width, height)
+    }
+
+    #[test]
+    fn hashes_identical_and_rescaled_images_alike() {
+        let (small, width, height) = image(1);
+        let (large, large_width, large_height) = image(4);
+        let hash = perceptual_hash(&small, width, height);
+        assert_eq!(hash, perceptual_hash(&small, width, height));
+        assert!(
+            (hash ^ perceptual_hash(&large, large_width, large_height)).count_ones()
+                <= MAX_DISTANCE
+        );
+
+        let flipped: Vec<f32> = small
+            .chunks(width)
+            .flat_map(|row| row.iter().rev())
+            .copied()
+            .collect();
+        assert!((hash ^ perceptual_hash(&flipped, width, height)).count_ones() > MAX_DISTANCE);
+    }
+
+    #[test]
+    fn groups_identical_hashes() {
+        let pairs = vec![
+            pair("A", 0, false),
+            pair("B", 0, false),
+            pair("C", 0, false),
+        ];
+        let groups = find_duplicates(
+            &pairs,
+            &[(0, 0xABCD, 1.0), (1, 0xFFFF_0000, 1.0), (2, 0xABCD, 1.0)],
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, [0, 2]);
+    }
+
+    #[test]
+    fn does_not_chain_similar_frames() {
+        // Each frame is within reach of the next but the first and last
+        // differ by twice as much
+        let pairs = vec![
+            pair("A", 0, false),
+            pair("B", 0, false),
+            pair("C", 0, false),
+        ];
+        let groups = find_duplicates(&pairs, &[(0, 0, 1.0), (1, 0xF, 1.0), (2, 0xFF, 1.0)]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, [0, 1]);
+    }
+
+    #[test]
+    fn keeps_rated_then_sharpest_then_complete_then_first() {
+        let keeper = |pairs: &[PhotoPair], sharpness: [f32; 3]| {
+            let photos: Vec<_> = (0..3).map(|idx| (idx, 0, sharpness[idx])).collect();
+            find_duplicates(pairs, &photos)[0].keeper
+        };
+        let plain = [
+            pair("A", 0, false),
+            pair("B", 0, false),
+            pair("C", 0, false),
+        ];
+        assert_eq!(keeper(&plain, [1.0, 1.0, 1.0]), 0);
+        assert_eq!(keeper(&plain, [1.0, 3.0, 2.0]), 1);
+
+        let rated = [
+            pair("A", 0, false),
+            pair("B", 0, false),
+            pair("C", 2, false),
+        ];
+        assert_eq!(keeper(&rated, [1.0, 3.0, 2.0]), 2);
+
+        let complete = [pair("A", 0, false), pair("B", 0, true), pair("C", 0, false)];
+        assert_eq!(keeper(&complete, [1.0, 1.0, 1.0]), 1);
+        assert_eq!(keeper(&complete, [2.0, 1.0, 1.0]), 0);
+    }
+}
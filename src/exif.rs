@@ -0,0 +1,449 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::raf::RafFile;
+use crate::tiff::{Ifd, Tiff};
+
+/// How much of a JPEG to read when looking for its APP1 block. APP1 is
+/// capped at 64 KB but may follow an APP0 segment.
+const JPEG_HEAD_BYTES: usize = 128 * 1024;
+/// TIFF-based RAW files keep their EXIF IFD near the start of the file.
+const RAW_HEAD_BYTES: usize = 1024 * 1024;
+
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_EXPOSURE_TIME: u16 = 0x829A;
+const TAG_F_NUMBER: u16 = 0x829D;
+const TAG_ISO: u16 = 0x8827;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+const TAG_EXPOSURE_BIAS: u16 = 0x9204;
+const TAG_FOCAL_LENGTH: u16 = 0x920A;
+const TAG_MAKER_NOTE: u16 = 0x927C;
+const TAG_PIXEL_X_DIMENSION: u16 = 0xA002;
+const TAG_PIXEL_Y_DIMENSION: u16 = 0xA003;
+const TAG_FOCAL_LENGTH_35MM: u16 = 0xA405;
+const TAG_LENS_MODEL: u16 = 0xA434;
+
+const FUJI_MAKER_NOTE_MAGIC: &[u8] = b"FUJIFILM";
+const FUJI_TAG_SATURATION: u16 = 0x1003;
+const FUJI_TAG_FOCUS_PIXEL: u16 = 0x1023;
+const FUJI_TAG_SEQUENCE_NUMBER: u16 = 0x1101;
+const FUJI_TAG_FILM_MODE: u16 = 0x1401;
+
+/// Shooting data shown in the info panel.
+#[derive(Debug, Clone, Default)]
+pub struct ExifData {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// Exposure time as a (numerator, denominator) fraction of a second.
+    pub exposure_time: Option<(i64, i64)>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    pub focal_length: Option<f64>,
+    pub focal_length_35mm: Option<u32>,
+    pub exposure_bias: Option<f64>,
+    pub date_time_original: Option<String>,
+    /// Fractional seconds of `date_time_original`, as digits after the
+    /// decimal point.
+    pub sub_sec_time_original: Option<String>,
+    /// EXIF orientation, 1 (upright) to 8.
+    pub orientation: Option<u16>,
+    /// Fujifilm film simulation from the maker notes.
+    pub film_simulation: Option<String>,
+    /// Position within a continuous shooting sequence, counting from 1.
+    pub sequence_number: Option<u32>,
+    /// Fujifilm focus point as a fraction of the image's width and height,
+    /// in the stored orientation.
+    pub focus_point: Option<(f32, f32)>,
+}
+
+impl ExifData {
+    /// Capture time in seconds, with the fraction when the camera recorded
+    /// one. Only meaningful for comparing photos from the same camera.
+    pub fn capture_time(&self) -> Option<f64> {
+        let text = self.date_time_original.as_deref()?.trim();
+        let time = chrono::NaiveDateTime::parse_from_str(text, "%Y:%m:%d %H:%M:%S").ok()?;
+        let fraction = self
+            .sub_sec_time_original
+            .as_deref()
+            .map(str::trim)
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| format!("0.{}", digits).parse::<f64>().ok())
+            .unwrap_or(0.0);
+        Some(time.and_utc().timestamp() as f64 + fraction)
+    }
+
+    /// The focus point as a fraction of the upright image's width and
+    /// height.
+    pub fn upright_focus_point(&self) -> Option<(f32, f32)> {
+        let (x, y) = self.focus_point?;
+        Some(match self.orientation.unwrap_or(1) {
+            2 => (1.0 - x, y),
+            3 => (1.0 - x, 1.0 - y),
+            4 => (x, 1.0 - y),
+            5 => (y, x),
+            6 => (1.0 - y, x),
+            7 => (1.0 - y, 1.0 - x),
+            8 => (y, 1.0 - x),
+            _ => (x, y),
+        })
+    }
+
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            // Most models already start with the make, e.g. "Canon EOS R5"
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.clone().or_else(|| model.clone()),
+        }
+    }
+
+    pub fn shutter_speed(&self) -> Option<String> {
+        let (num, den) = self.exposure_time?;
+        if num <= 0 || den <= 0 {
+            return None;
+        }
+        let seconds = num as f64 / den as f64;
+        Some(if seconds >= 0.3 {
+            format!("{:.1}\"", seconds).replace(".0\"", "\"")
+        } else {
+            format!("1/{:.0} s", 1.0 / seconds)
+        })
+    }
+
+    pub fn aperture(&self) -> Option<String> {
+        self.f_number.map(|f| format!("f/{:.1}", f).replace(".0", ""))
+    }
+
+    pub fn focal(&self) -> Option<String> {
+        let focal = self.focal_length?;
+        Some(match self.focal_length_35mm {
+            Some(eq) if eq as f64 != focal.round() => format!("{:.0} mm ({} mm eq.)", focal, eq),
+            _ => format!("{:.0} mm", focal),
+        })
+    }
+
+    pub fn exposure_compensation(&self) -> Option<String> {
+        self.exposure_bias.map(|ev| format!("{:+.1} EV", ev))
+    }
+}
+
+pub fn read_file(path: &Path) -> Option<ExifData> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    if ext == "jpg" || ext == "jpeg" {
+        parse_jpeg(&read_head(path, JPEG_HEAD_BYTES)?)
+    } else if ext == "raf" {
+        // RAF keeps its EXIF in the embedded JPEG
+        parse_jpeg(&RafFile::open(path)?.read_preview_head()?)
+    } else {
+        parse_tiff(&read_head(path, RAW_HEAD_BYTES)?)
+    }
+}
+
+/// The small JPEG thumbnail from IFD1 of a JPEG's or RAF preview's EXIF
+/// block, with the orientation needed to show it upright.
+pub fn read_thumbnail(path: &Path) -> Option<(Vec<u8>, Option<u16>)> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let head = if ext == "jpg" || ext == "jpeg" {
+        read_head(path, JPEG_HEAD_BYTES)?
+    } else if ext == "raf" {
+        RafFile::open(path)?.read_preview_head()?
+    } else {
+        return None;
+    };
+
+    let data = find_app1_exif(&head)?;
+    let tiff = Tiff::parse(data)?;
+    let ifd0 = tiff.ifd(tiff.first_ifd())?;
+    if ifd0.next == 0 {
+        return None;
+    }
+    let ifd1 = tiff.ifd(ifd0.next)?;
+    let offset = tiff.uint(ifd1.get(TAG_THUMBNAIL_OFFSET)?)? as usize;
+    let length = tiff.uint(ifd1.get(TAG_THUMBNAIL_LENGTH)?)? as usize;
+    let thumbnail = data.get(offset..offset.checked_add(length)?)?;
+    let orientation = ifd0
+        .get(TAG_ORIENTATION)
+        .and_then(|e| tiff.uint(e))
+        .map(|o| o as u16);
+    Some((thumbnail.to_vec(), orientation))
+}
+
+fn read_head(path: &Path, limit: usize) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(limit as u64)
+        .read_to_end(&mut data)
+        .ok()?;
+    Some(data)
+}
+
+/// Finds the APP1 "Exif" segment of a JPEG and parses its TIFF payload.
+pub fn parse_jpeg(data: &[u8]) -> Option<ExifData> {
+    parse_tiff(find_app1_exif(data)?)
+}
+
+/// Returns the TIFF block inside a JPEG's APP1 "Exif" segment.
+fn find_app1_exif(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Start of scan: no more metadata segments follow
+        if marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..(pos + 2 + length).min(data.len()))?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+pub fn parse_tiff(data: &[u8]) -> Option<ExifData> {
+    let tiff = Tiff::parse(data)?;
+    let ifd0 = tiff.ifd(tiff.first_ifd())?;
+    let exif_ifd = ifd0
+        .get(TAG_EXIF_IFD)
+        .and_then(|e| tiff.uint(e))
+        .and_then(|offset| tiff.ifd(offset));
+
+    let ascii = |ifd: &Ifd, tag| ifd.get(tag).and_then(|e| tiff.ascii(e));
+    let real = |ifd: &Ifd, tag| {
+        ifd.get(tag)
+            .and_then(|e| tiff.rational(e))
+            .filter(|&(_, den)| den != 0)
+            .map(|(num, den)| num as f64 / den as f64)
+    };
+    let uint = |ifd: &Ifd, tag| ifd.get(tag).and_then(|e| tiff.uint(e));
+
+    let mut exif = ExifData {
+        make: ascii(&ifd0, TAG_MAKE),
+        model: ascii(&ifd0, TAG_MODEL),
+        orientation: uint(&ifd0, TAG_ORIENTATION)
+            .map(|o| o as u16)
+            .filter(|o| (1..=8).contains(o)),
+        ..Default::default()
+    };
+
+    if let Some(ref ifd) = exif_ifd {
+        exif.lens = ascii(ifd, TAG_LENS_MODEL);
+        exif.exposure_time = ifd.get(TAG_EXPOSURE_TIME).and_then(|e| tiff.rational(e));
+        exif.f_number = real(ifd, TAG_F_NUMBER);
+        exif.iso = uint(ifd, TAG_ISO);
+        exif.focal_length = real(ifd, TAG_FOCAL_LENGTH);
+        exif.focal_length_35mm = uint(ifd, TAG_FOCAL_LENGTH_35MM).filter(|&f| f > 0);
+        exif.exposure_bias = real(ifd, TAG_EXPOSURE_BIAS);
+        exif.date_time_original = ascii(ifd, TAG_DATE_TIME_ORIGINAL);
+        exif.sub_sec_time_original = ascii(ifd, TAG_SUB_SEC_TIME_ORIGINAL);
+
+        if let Some((note, fuji)) = ifd
+            .get(TAG_MAKER_NOTE)
+            .and_then(|e| tiff.value_bytes(e))
+            .and_then(fuji_maker_note)
+        {
+            exif.film_simulation = fuji_film_simulation(&note, &fuji);
+            exif.sequence_number = fuji
+                .get(FUJI_TAG_SEQUENCE_NUMBER)
+                .and_then(|e| note.uint(e))
+                .filter(|&n| n > 0);
+            let size = uint(ifd, TAG_PIXEL_X_DIMENSION).zip(uint(ifd, TAG_PIXEL_Y_DIMENSION));
+            exif.focus_point = fuji
+                .get(FUJI_TAG_FOCUS_PIXEL)
+                .and_then(|e| note.uints(e))
+                .zip(size)
+                .and_then(|(pixel, size)| fuji_focus_point(&pixel, size));
+        }
+    }
+
+    Some(exif)
+}
+
+/// Opens a Fujifilm maker note. The note is always little-endian with
+/// offsets relative to its own start.
+fn fuji_maker_note(maker_note: &[u8]) -> Option<(Tiff<'_>, Ifd)> {
+    if !maker_note.starts_with(FUJI_MAKER_NOTE_MAGIC) {
+        return None;
+    }
+    let note = Tiff::headerless(maker_note, false);
+    let ifd = note.ifd(note.u32_at(8)?)?;
+    Some((note, ifd))
+}
+
+/// Converts a Fujifilm focus pixel to a fraction of the image size. The
+/// pixel is given in full-size image coordinates.
+fn fuji_focus_point(pixel: &[u32], (width, height): (u32, u32)) -> Option<(f32, f32)> {
+    let [x, y] = *pixel else {
+        return None;
+    };
+    if width == 0 || height == 0 || x > width || y > height {
+        return None;
+    }
+    Some((x as f32 / width as f32, y as f32 / height as f32))
+}
+
+/// Reads the film simulation from a Fujifilm maker note.
+fn fuji_film_simulation(note: &Tiff, ifd: &Ifd) -> Option<String> {
+    // Monochrome simulations are stored as a saturation setting
+    let saturation = ifd.get(FUJI_TAG_SATURATION).and_then(|e| note.uint(e));
+    let monochrome = match saturation {
+        Some(0x300) => Some("Monochrome"),
+        Some(0x301) => Some("Monochrome + R"),
+        Some(0x302) => Some("Monochrome + Ye"),
+        Some(0x303) => Some("Monochrome + G"),
+        Some(0x310) => Some("Sepia"),
+        Some(0x500) => Some("Acros"),
+        Some(0x501) => Some("Acros + R"),
+        Some(0x502) => Some("Acros + Ye"),
+        Some(0x503) => Some("Acros + G"),
+        _ => None,
+    };
+    if let Some(name) = monochrome {
+        return Some(name.to_string());
+    }
+
+    let name = match ifd.get(FUJI_TAG_FILM_MODE).and_then(|e| note.uint(e))? {
+        0x000 => "Provia / Standard",
+        0x100 | 0x110 | 0x130 | 0x300 => "Studio Portrait",
+        0x120 => "Astia / Soft",
+        0x200 | 0x400 => "Velvia / Vivid",
+        0x500 => "Pro Neg. Std",
+        0x501 => "Pro Neg. Hi",
+        0x600 => "Classic Chrome",
+        0x700 => "Eterna",
+        0x800 => "Classic Negative",
+        0x900 => "Eterna Bleach Bypass",
+        0xA00 => "Nostalgic Neg.",
+        0xB00 => "Reala Ace",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Entry = (u16, u16, u32, Vec<u8>);
+
+    /// An IFD to be placed at `pos` in its file, with values longer than 4
+    /// bytes stored right after it.
+    fn ifd(big_endian: bool, pos: usize, entries: &[Entry]) -> Vec<u8> {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let mut data = u16_bytes(entries.len() as u16).to_vec();
+        let mut extra_pos = pos + 2 + entries.len() * 12 + 4;
+        let mut extra: Vec<u8> = Vec::new();
+        for (tag, kind, count, value) in entries {
+            data.extend(u16_bytes(*tag));
+            data.extend(u16_bytes(*kind));
+            data.extend(u32_bytes(*count));
+            if value.len() <= 4 {
+                let mut field = value.clone();
+                field.resize(4, 0);
+                data.extend(field);
+            } else {
+                data.extend(u32_bytes(extra_pos as u32));
+                extra.extend(value);
+                extra_pos += value.len();
+            }
+        }
+        data.extend(u32_bytes(0));
+        data.extend(extra);
+        data
+    }
+
+    fn fuji_note(entries: &[Entry]) -> Vec<u8> {
+        let mut note = FUJI_MAKER_NOTE_MAGIC.to_vec();
+        note.extend(12u32.to_le_bytes());
+        note.extend(ifd(false, 12, entries));
+        note
+    }
+
+    fn short(value: u16) -> Entry {
+        (FUJI_TAG_FILM_MODE, 3, 1, value.to_le_bytes().to_vec())
+    }
+
+    /// A TIFF with a Make in IFD0 and an EXIF IFD holding the ISO, aperture
+    /// and `maker_note`.
+    fn exif_tiff(big_endian: bool, maker_note: Vec<u8>) -> Vec<u8> {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let make = (TAG_MAKE, 2, 9, b"FUJIFILM\0".to_vec());
+        let ifd0_len = ifd(big_endian, 8, &[make.clone(), (TAG_EXIF_IFD, 4, 1, vec![0; 4])]).len();
+        let exif_pos = 8 + ifd0_len;
+
+        let mut data = if big_endian { b"MM".to_vec() } else { b"II".to_vec() };
+        data.extend(u16_bytes(42));
+        data.extend(u32_bytes(8));
+        let exif_offset = u32_bytes(exif_pos as u32).to_vec();
+        data.extend(ifd(big_endian, 8, &[make, (TAG_EXIF_IFD, 4, 1, exif_offset)]));
+        let f_number: Vec<u8> = [28u32, 10].into_iter().flat_map(u32_bytes).collect();
+        data.extend(ifd(
+            big_endian,
+            exif_pos,
+            &[
+                (TAG_F_NUMBER, 5, 1, f_number),
+                (TAG_ISO, 3, 1, u16_bytes(640).to_vec()),
+                (TAG_MAKER_NOTE, 7, maker_note.len() as u32, maker_note),
+            ],
+        ));
+        data
+    }
+
+    fn check(big_endian: bool) {
+        let data = exif_tiff(big_endian, fuji_note(&[short(0x600)]));
+        let exif = parse_tiff(&data).unwrap();
+        assert_eq!(exif.make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(exif.iso, Some(640));
+        assert_eq!(exif.f_number, Some(2.8));
+        assert_eq!(exif.film_simulation.as_deref(), Some("Classic Chrome"));
+    }
+
+    #[test]
+    fn parses_little_endian_exif() {
+        check(false);
+    }
+
+    #[test]
+    fn parses_big_endian_exif() {
+        check(true);
+    }
+
+    fn film_simulation(entries: &[Entry]) -> Option<String> {
+        let note = fuji_note(entries);
+        let (note, ifd) = fuji_maker_note(&note).unwrap();
+        fuji_film_simulation(&note, &ifd)
+    }
+
+    #[test]
+    fn maps_fuji_film_modes() {
+        assert_eq!(film_simulation(&[short(0x000)]).as_deref(), Some("Provia / Standard"));
+        assert_eq!(film_simulation(&[short(0x120)]).as_deref(), Some("Astia / Soft"));
+        assert_eq!(film_simulation(&[short(0x200)]).as_deref(), Some("Velvia / Vivid"));
+        assert_eq!(film_simulation(&[short(0x800)]).as_deref(), Some("Classic Negative"));
+        assert_eq!(film_simulation(&[short(0xFFF)]), None);
+    }
+
+    #[test]
+    fn monochrome_overrides_film_mode() {
+        let acros = (FUJI_TAG_SATURATION, 3, 1, 0x501u16.to_le_bytes().to_vec());
+        assert_eq!(film_simulation(&[acros, short(0x000)]).as_deref(), Some("Acros + R"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Histogram and clipping of the displayed image, for judging exposure.
+
+use eframe::egui::{self, Color32, Pos2, Sense, Stroke, epaint::Mesh, pos2, vec2};
+
+use crate::analysis::{self, Clipping};
+
+/// Levels per channel.
+const BINS: usize = 256;
+const HISTOGRAM_HEIGHT: f32 = 100.0;
+
+/// Pixel counts per level for each colour channel and the luma.
+pub struct Histogram {
+    pub red: [u32; BINS],
+    pub green: [u32; BINS],
+    pub blue: [u32; BINS],
+    pub luma: [u32; BINS],
+}
+
+/// Exposure of a display image, measured once when it is decoded.
+pub struct DisplayStats {
+    pub histogram: Histogram,
+    /// Clipping of every pixel, row by row.
+    pub clipping: Vec<Clipping>,
+}
+
+impl DisplayStats {
+    /// Measures RGBA pixels.
+    pub fn measure(pixels: &[u8]) -> Self {
+        let mut histogram = Histogram {
+            red: [0; BINS],
+            green: [0; BINS],
+            blue: [0; BINS],
+            luma: [0; BINS],
+        };
+        let mut clipping = Vec::with_capacity(pixels.len() / 4);
+        for pixel in pixels.chunks_exact(4) {
+            histogram.red[pixel[0] as usize] += 1;
+            histogram.green[pixel[1] as usize] += 1;
+            histogram.blue[pixel[2] as usize] += 1;
+            histogram.luma[(analysis::luma(pixel).round() as usize).min(BINS - 1)] += 1;
+            clipping.push(analysis::clipping(pixel));
+        }
+        Self {
+            histogram,
+            clipping,
+        }
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.clipping.len()
+    }
+}
+
+impl Histogram {
+    /// Draws the luma as a grey area with the colour channels as lines on
+    /// top, across the available width.
+    pub fn show(&self, ui: &mut egui::Ui) {
+        let (rect, _) =
+            ui.allocate_exact_size(vec2(ui.available_width(), HISTOGRAM_HEIGHT), Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+        // Scale to the tallest level away from the ends, so a spike of
+        // clipped pixels doesn't flatten the rest
+        let peak = [&self.red, &self.green, &self.blue, &self.luma]
+            .iter()
+            .flat_map(|channel| channel[1..BINS - 1].iter())
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1) as f32;
+        let point = |level: usize, count: u32| {
+            let x = rect.left() + level as f32 / (BINS - 1) as f32 * rect.width();
+            let y = rect.bottom() - (count as f32 / peak).min(1.0) * rect.height();
+            pos2(x, y)
+        };
+
+        let mut area = Mesh::default();
+        let fill = Color32::from_gray(110);
+        for (level, &count) in self.luma.iter().enumerate() {
+            let top = point(level, count);
+            let base = area.vertices.len() as u32;
+            area.colored_vertex(top, fill);
+            area.colored_vertex(pos2(top.x, rect.bottom()), fill);
+            if level > 0 {
+                area.add_triangle(base - 2, base - 1, base);
+                area.add_triangle(base - 1, base, base + 1);
+            }
+        }
+        painter.add(area);
+
+        let channels = [
+            (&self.red, Color32::from_rgb(230, 60, 60)),
+            (&self.green, Color32::from_rgb(60, 200, 60)),
+            (&self.blue, Color32::from_rgb(70, 110, 240)),
+        ];
+        for (channel, color) in channels {
+            let points: Vec<Pos2> = channel
+                .iter()
+                .enumerate()
+                .map(|(level, &count)| point(level, count))
+                .collect();
+            painter.add(egui::Shape::line(points, Stroke::new(1.0, color)));
+        }
+        painter.rect_stroke(rect, 2.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    }
+}
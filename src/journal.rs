@@ -0,0 +1,83 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deletion::DeletionMode;
+use crate::photo_pair::DeletionAction;
+
+/// Journal file kept in the opened folder, one JSON batch per line.
+const JOURNAL_FILE_NAME: &str = ".photoculler-journal.jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub original: PathBuf,
+    /// Where the file was moved to; `None` when it was deleted permanently.
+    pub staged: Option<PathBuf>,
+    pub action: DeletionAction,
+}
+
+/// Everything removed by a single confirmation of the delete dialog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletionBatch {
+    pub timestamp: String,
+    pub mode: DeletionMode,
+    pub entries: Vec<JournalEntry>,
+}
+
+impl DeletionBatch {
+    pub fn new(mode: DeletionMode) -> Self {
+        Self {
+            timestamp: chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            mode,
+            entries: Vec::new(),
+        }
+    }
+}
+
+pub fn append_batch(dir: &Path, batch: &DeletionBatch) -> io::Result<()> {
+    let line = serde_json::to_string(batch)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(JOURNAL_FILE_NAME))?;
+    writeln!(file, "{}", line)
+}
+
+pub fn last_batch(dir: &Path) -> io::Result<Option<DeletionBatch>> {
+    Ok(read_batches(dir)?.pop())
+}
+
+/// Keeps only `entries` of the most recent batch once the rest has been
+/// undone, dropping the batch when none are left.
+pub fn retain_last_batch(dir: &Path, entries: Vec<JournalEntry>) -> io::Result<()> {
+    let mut batches = read_batches(dir)?;
+    if let Some(mut batch) = batches.pop()
+        && !entries.is_empty()
+    {
+        batch.entries = entries;
+        batches.push(batch);
+    }
+
+    let mut contents = String::new();
+    for batch in &batches {
+        contents.push_str(&serde_json::to_string(batch)?);
+        contents.push('\n');
+    }
+    fs::write(dir.join(JOURNAL_FILE_NAME), contents)
+}
+
+fn read_batches(dir: &Path) -> io::Result<Vec<DeletionBatch>> {
+    let contents = match fs::read_to_string(dir.join(JOURNAL_FILE_NAME)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+        .collect()
+}
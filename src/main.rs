@@ -1,12 +1,167 @@
+mod analysis;
+mod burst;
 mod deletion;
+mod develop;
+mod duplicates;
+mod exif;
+mod histogram;
+mod journal;
+mod peaking;
 mod photo_pair;
+mod raf;
 mod scanner;
+mod session;
+mod tiff;
 mod viewer;
+mod xmp;
+mod zoom;
 
 use eframe::egui;
-use photo_pair::{DeletionAction, PhotoPair};
+use analysis::{Analysis, Analyzer};
+use deletion::DeletionMode;
+use duplicates::DuplicateGroup;
+use photo_pair::{ColorLabel, DeletionAction, MAX_RATING, PhotoPair};
+use session::{ScanSettings, Session};
+use std::collections::{BTreeSet, HashSet};
 use std::path::PathBuf;
-use viewer::ImageCache;
+use std::time::{Duration, Instant};
+use viewer::{ImageCache, ImageKey, RenderMode};
+use zoom::{ImageLayout, ZoomView};
+
+/// Ctrl+0 to Ctrl+5 set the star rating.
+const RATING_KEYS: [egui::Key; 6] = [
+    egui::Key::Num0,
+    egui::Key::Num1,
+    egui::Key::Num2,
+    egui::Key::Num3,
+    egui::Key::Num4,
+    egui::Key::Num5,
+];
+
+/// 6 to 9 toggle the colour labels, as in Lightroom.
+const COLOR_LABEL_KEYS: [(egui::Key, ColorLabel); 4] = [
+    (egui::Key::Num6, ColorLabel::Red),
+    (egui::Key::Num7, ColorLabel::Yellow),
+    (egui::Key::Num8, ColorLabel::Green),
+    (egui::Key::Num9, ColorLabel::Blue),
+];
+
+/// How long the current photo must stay the same before a change of
+/// position alone is saved to the session file.
+const POSITION_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// Choices offered in View > Cache for the image cache memory budget.
+const MEMORY_BUDGET_CHOICES_MB: [usize; 6] = [256, 512, 1024, 2048, 4096, 8192];
+
+fn label_color(label: ColorLabel) -> egui::Color32 {
+    match label {
+        ColorLabel::Red => egui::Color32::from_rgb(220, 60, 60),
+        ColorLabel::Yellow => egui::Color32::from_rgb(230, 200, 50),
+        ColorLabel::Green => egui::Color32::from_rgb(70, 180, 80),
+        ColorLabel::Blue => egui::Color32::from_rgb(70, 120, 220),
+        ColorLabel::Purple => egui::Color32::from_rgb(160, 90, 200),
+    }
+}
+
+/// Height of filmstrip thumbnails, in points.
+const FILMSTRIP_THUMB_HEIGHT: f32 = 72.0;
+/// Width of each filmstrip cell, in points.
+const FILMSTRIP_ITEM_WIDTH: f32 = 112.0;
+/// Pairs either side of the visible part of the filmstrip whose thumbnails
+/// are decoded ahead of scrolling.
+const FILMSTRIP_LOOKAHEAD: usize = 10;
+
+/// Size of each contact sheet cell, in points.
+const GRID_CELL_WIDTH: f32 = 176.0;
+const GRID_CELL_HEIGHT: f32 = 136.0;
+/// Rows above and below the visible part of the grid whose thumbnails are
+/// decoded ahead of scrolling.
+const GRID_LOOKAHEAD_ROWS: usize = 2;
+
+/// Most pairs shown side by side in the compare view.
+const MAX_COMPARE: usize = 4;
+/// Shift+1 to Shift+4 keep that compare candidate and reject the others.
+const PROMOTE_KEYS: [egui::Key; MAX_COMPARE] = [
+    egui::Key::Num1,
+    egui::Key::Num2,
+    egui::Key::Num3,
+    egui::Key::Num4,
+];
+/// Height of the name and action row above each compared photo.
+const COMPARE_HEADER_HEIGHT: f32 = 24.0;
+
+/// Filmstrip badge for an action: its shortcut letter and a colour.
+fn action_badge(action: DeletionAction) -> (&'static str, egui::Color32) {
+    match action {
+        DeletionAction::KeepBoth => ("K", egui::Color32::from_rgb(70, 180, 80)),
+        DeletionAction::DeleteRaw => ("R", egui::Color32::from_rgb(230, 150, 40)),
+        DeletionAction::DeleteJpeg => ("J", egui::Color32::from_rgb(230, 200, 50)),
+        DeletionAction::DeleteBoth => ("B", egui::Color32::from_rgb(220, 60, 60)),
+    }
+}
+
+/// Order photos are navigated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SortOrder {
+    /// Folder and file name, which is shooting order.
+    #[default]
+    Name,
+    /// Softest first; photos not analysed yet come last.
+    Sharpness,
+}
+
+impl SortOrder {
+    const ALL: [SortOrder; 2] = [SortOrder::Name, SortOrder::Sharpness];
+
+    fn label(&self) -> &'static str {
+        match self {
+            SortOrder::Name => "File Name",
+            SortOrder::Sharpness => "Sharpness (softest first)",
+        }
+    }
+}
+
+/// Which photos navigation, the filmstrip and the grid show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum PhotoFilter {
+    #[default]
+    All,
+    /// Photos analysed as softer than the threshold.
+    Soft,
+    /// Photos with more clipped highlights than the threshold.
+    Overexposed,
+    /// Photos with more clipped shadows than the threshold, or dark overall.
+    Underexposed,
+}
+
+impl PhotoFilter {
+    const ALL: [PhotoFilter; 4] = [
+        PhotoFilter::All,
+        PhotoFilter::Soft,
+        PhotoFilter::Overexposed,
+        PhotoFilter::Underexposed,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            PhotoFilter::All => "All Photos",
+            PhotoFilter::Soft => "Soft Only",
+            PhotoFilter::Overexposed => "Clipped Highlights",
+            PhotoFilter::Underexposed => "Underexposed",
+        }
+    }
+}
+
+/// Colours offered for focus peaking.
+const PEAKING_COLORS: [(&str, egui::Color32); 4] = [
+    ("Red", egui::Color32::from_rgb(255, 40, 40)),
+    ("Yellow", egui::Color32::from_rgb(255, 230, 0)),
+    ("Green", egui::Color32::from_rgb(40, 255, 60)),
+    ("Cyan", egui::Color32::from_rgb(0, 230, 255)),
+];
+
+/// Colour of the flags for soft and badly exposed photos.
+const WARNING_COLOR: egui::Color32 = egui::Color32::from_rgb(230, 140, 40);
 
 fn main() -> eframe::Result<()> {
     println!("Starting Photo Culler");
@@ -21,7 +176,16 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Photo Culler - Fuji",
         options,
-        Box::new(|_cc| Ok(Box::new(PhotoCullerApp::default()))),
+        Box::new(|_cc| {
+            Ok(Box::new(PhotoCullerApp {
+                show_filmstrip: true,
+                soft_threshold: analysis::DEFAULT_SOFT_THRESHOLD,
+                clipping_threshold: analysis::DEFAULT_CLIPPING_THRESHOLD,
+                peaking_threshold: peaking::DEFAULT_THRESHOLD,
+                peaking_color: PEAKING_COLORS[0].1,
+                ..Default::default()
+            }))
+        }),
     )
 }
 
@@ -32,20 +196,95 @@ struct PhotoCullerApp {
     folder_path: Option<PathBuf>,
     image_cache: ImageCache,
     show_delete_dialog: bool,
+    deletion_mode: DeletionMode,
     status_message: Option<String>,
+    session_dirty: bool,
+    saved_index: usize,
+    /// Position not saved yet and when it was reached; saving waits until
+    /// browsing settles.
+    unsaved_position: Option<(usize, Instant)>,
+    scan_settings: ScanSettings,
+    show_info_panel: bool,
+    /// Show the developed RAW instead of the JPEG where possible.
+    show_raw: bool,
+    /// Paint clipped highlights and shadows over the photo.
+    show_clipping: bool,
+    /// Highlight in-focus edges of the photo.
+    show_peaking: bool,
+    /// Edge strength from which edges are highlighted.
+    peaking_threshold: u8,
+    peaking_color: egui::Color32,
+    /// Zoomed-in view of the current photo; `None` fits it to the panel.
+    zoom: Option<ZoomView>,
+    /// Keep the zoom and position when moving to another photo.
+    zoom_locked: bool,
+    /// Photo the zoom belongs to, to reset it on navigation.
+    zoom_index: usize,
+    show_filmstrip: bool,
+    /// Photo the filmstrip was last scrolled to.
+    filmstrip_index: Option<usize>,
+    /// Show the contact sheet instead of a single photo.
+    show_grid: bool,
+    /// Pairs selected in the grid; edits apply to all of them.
+    selection: BTreeSet<usize>,
+    /// Fixed end of a shift-click range.
+    selection_anchor: Option<usize>,
+    /// Rubber band being dragged: its start in grid content coordinates and
+    /// the selection it adds to.
+    rubber_band: Option<(egui::Pos2, BTreeSet<usize>)>,
+    /// Photo the grid was last scrolled to.
+    grid_index: Option<usize>,
+    /// Columns in the last grid layout, for moving up and down.
+    grid_columns: usize,
+    /// Pairs shown side by side, two to four of them; empty when not
+    /// comparing.
+    compare: Vec<usize>,
+    /// Bursts shown frame by frame instead of as a single stack.
+    expanded_bursts: HashSet<u32>,
+    /// Groups found by the last duplicate search.
+    duplicate_groups: Vec<DuplicateGroup>,
+    /// Show the duplicate groups for review instead of a single photo.
+    show_duplicates: bool,
+    /// Ask before marking the extras of every duplicate group.
+    show_reject_duplicates_dialog: bool,
+    /// Sharpness, exposure and hashes, computed in the background.
+    analyzer: Analyzer,
+    sort_order: SortOrder,
+    filter: PhotoFilter,
+    /// Sharpness below which a photo is flagged as soft.
+    soft_threshold: f32,
+    /// Fraction of clipped highlights or shadows above which a photo is
+    /// flagged as over- or underexposed.
+    clipping_threshold: f32,
+    detected_raw_extensions: Vec<String>,
+    /// EXIF of the open folder's files, so rescans only read new files.
+    exif_cache: scanner::ExifCache,
 }
 
 impl PhotoCullerApp {
     fn open_folder(&mut self) {
         if let Some(path) = rfd::FileDialog::new().pick_folder() {
             println!("Opening folder: {}", path.display());
-            match scanner::scan_directory(&path) {
+            self.save_session();
+            self.exif_cache.clear();
+            match scanner::scan_directory(&path, &mut self.exif_cache) {
                 Ok(pairs) => {
                     println!("Loaded {} photo pairs", pairs.len());
+                    let session = Session::load(&path);
+                    self.current_index = session.current_index(&pairs).unwrap_or(0);
+                    self.scan_settings = session.scan;
+                    self.saved_index = self.current_index;
                     self.pairs = pairs;
-                    self.current_index = 0;
+                    self.clear_selection();
+                    self.compare.clear();
+                    self.expanded_bursts.clear();
+                    self.duplicate_groups.clear();
+                    self.show_duplicates = false;
                     self.folder_path = Some(path);
+                    self.refresh_detected_raw_extensions();
                     self.image_cache.clear();
+                    self.analyzer.clear();
+                    self.analyzer.analyze(&self.pairs);
                     self.status_message = Some(format!("Loaded {} photo pairs", self.pairs.len()));
                 }
                 Err(e) => {
@@ -56,25 +295,1088 @@ impl PhotoCullerApp {
         }
     }
 
-    fn next_image(&mut self) {
-        if !self.pairs.is_empty() && self.current_index < self.pairs.len() - 1 {
-            self.current_index += 1;
+    /// Saves decisions as soon as they change. A change of position alone is
+    /// saved once it has held for `POSITION_SAVE_DELAY`, when the window
+    /// loses focus or when the app closes, rather than on every key press.
+    fn autosave_session(&mut self, ctx: &egui::Context) {
+        let (focused, closing) = ctx.input(|i| (i.focused, i.viewport().close_requested()));
+        if !self.session_dirty && focused && !closing {
+            if self.saved_index == self.current_index {
+                self.unsaved_position = None;
+                return;
+            }
+            let changed_at = match self.unsaved_position {
+                Some((index, changed_at)) if index == self.current_index => changed_at,
+                _ => {
+                    let now = Instant::now();
+                    self.unsaved_position = Some((self.current_index, now));
+                    now
+                }
+            };
+            let remaining = POSITION_SAVE_DELAY.saturating_sub(changed_at.elapsed());
+            if !remaining.is_zero() {
+                ctx.request_repaint_after(remaining);
+                return;
+            }
+        }
+        self.save_session();
+    }
+
+    /// Writes decisions and position to the folder's session file if they
+    /// changed since the last save.
+    fn save_session(&mut self) {
+        if !self.session_dirty && self.saved_index == self.current_index {
+            return;
+        }
+        if let Some(ref folder) = self.folder_path {
+            let session =
+                Session::from_pairs(&self.pairs, self.current_index, self.scan_settings.clone());
+            if let Err(e) = session.save(folder) {
+                eprintln!("Failed to save session: {}", e);
+            }
+        }
+        self.session_dirty = false;
+        self.saved_index = self.current_index;
+        self.unsaved_position = None;
+    }
+
+    /// Re-reads the current folder after files were moved on disk.
+    fn rescan(&mut self) {
+        // The scan reapplies the session, so it must be up to date first
+        self.save_session();
+        self.image_cache.clear();
+        if let Some(ref path) = self.folder_path
+            && let Ok(pairs) = scanner::scan_directory(path, &mut self.exif_cache)
+        {
+            self.pairs = pairs;
+            self.clear_selection();
+            self.compare.clear();
+            self.expanded_bursts.clear();
+            // Groups hold pair indices, which a rescan invalidates
+            self.duplicate_groups.clear();
+            self.show_duplicates = false;
+            self.current_index = self.current_index.min(self.pairs.len().saturating_sub(1));
+            self.analyzer.analyze(&self.pairs);
+        }
+    }
+
+    fn refresh_detected_raw_extensions(&mut self) {
+        if let Some(ref folder) = self.folder_path {
+            self.detected_raw_extensions =
+                scanner::detect_raw_extensions(folder, self.scan_settings.recursive)
+                    .unwrap_or_default();
+        }
+    }
+
+    /// RAW extensions currently used to pair files.
+    fn effective_raw_extensions(&self) -> &[String] {
+        self.scan_settings
+            .raw_extensions
+            .as_deref()
+            .unwrap_or(&self.detected_raw_extensions)
+    }
+
+    fn toggle_raw_extension(&mut self, ext: &str) {
+        let current = self.effective_raw_extensions();
+        let enabled = !current.iter().any(|e| e == ext);
+        let extensions = scanner::KNOWN_RAW_EXTENSIONS
+            .iter()
+            .filter(|known| {
+                if **known == ext {
+                    enabled
+                } else {
+                    current.iter().any(|e| e == *known)
+                }
+            })
+            .map(|known| known.to_string())
+            .collect();
+        self.set_raw_extensions(Some(extensions));
+    }
+
+    fn set_raw_extensions(&mut self, extensions: Option<Vec<String>>) {
+        self.scan_settings.raw_extensions = extensions;
+        self.session_dirty = true;
+        self.rescan();
+    }
+
+    fn set_recursive(&mut self, recursive: bool) {
+        self.scan_settings.recursive = recursive;
+        self.session_dirty = true;
+        self.refresh_detected_raw_extensions();
+        self.rescan();
+    }
+
+    fn delete_marked(&mut self) {
+        let Some(folder) = self.folder_path.clone() else {
+            return;
+        };
+        match deletion::execute_deletions(&self.pairs, self.deletion_mode, &folder) {
+            Ok(count) => {
+                self.status_message = Some(format!("{}: {} files", self.deletion_mode.label(), count));
+            }
+            Err(errors) => {
+                self.status_message = Some(format!("Errors during deletion: {}", errors.join(", ")));
+            }
+        }
+        // Rescan even after partial failures, some files may have moved
+        self.rescan();
+    }
+
+    fn undo_last_delete(&mut self) {
+        let Some(folder) = self.folder_path.clone() else {
+            return;
+        };
+        match deletion::undo_last_deletion(&folder) {
+            Ok(0) => {
+                self.status_message = Some("Nothing to undo".to_string());
+            }
+            Ok(count) => {
+                self.status_message = Some(format!("Restored {} files", count));
+            }
+            Err(errors) => {
+                self.status_message = Some(format!("Errors during undo: {}", errors.join(", ")));
+            }
         }
+        self.rescan();
+    }
+
+    fn next_image(&mut self) {
+        self.step(1);
     }
 
     fn prev_image(&mut self) {
-        if self.current_index > 0 {
-            self.current_index -= 1;
+        self.step(-1);
+    }
+
+    /// Moves `steps` photos forward or back through the visible ones.
+    fn step(&mut self, steps: isize) {
+        let visible = self.visible_indices();
+        if visible.is_empty() {
+            return;
         }
+        let pos = self.visible_position(&visible) as isize + steps;
+        self.current_index = visible[pos.clamp(0, visible.len() as isize - 1) as usize];
+    }
+
+    /// Pairs shown when navigating: those passing the filter, in the chosen
+    /// order. A collapsed burst shows only its first frame that passes.
+    /// Sorting makes this costly, so `update` computes it once per frame for
+    /// the panels.
+    fn visible_indices(&self) -> Vec<usize> {
+        let mut visible: Vec<usize> = Vec::new();
+        for idx in (0..self.pairs.len()).filter(|&idx| self.passes_filter(idx)) {
+            let stacked = match self.pairs[idx].burst {
+                Some(burst) if !self.expanded_bursts.contains(&burst) => visible
+                    .last()
+                    .is_some_and(|&last| self.pairs[last].burst == Some(burst)),
+                _ => false,
+            };
+            if !stacked {
+                visible.push(idx);
+            }
+        }
+        if self.sort_order == SortOrder::Sharpness {
+            let sharpness =
+                |idx: usize| self.analysis(idx).map_or(f32::INFINITY, |a| a.sharpness);
+            visible.sort_by(|&a, &b| sharpness(a).total_cmp(&sharpness(b)));
+        }
+        visible
+    }
+
+    /// Position of the current pair in `visible`, of the frame standing for
+    /// its burst when the burst is collapsed, or of the nearest photo before
+    /// it when it is filtered out.
+    fn visible_position(&self, visible: &[usize]) -> usize {
+        let burst = self.current_pair().and_then(|p| p.burst);
+        visible
+            .iter()
+            .position(|&idx| idx == self.current_index)
+            .or_else(|| {
+                let burst = burst?;
+                visible.iter().position(|&idx| self.pairs[idx].burst == Some(burst))
+            })
+            .or_else(|| visible.iter().rposition(|&idx| idx <= self.current_index))
+            .unwrap_or(0)
     }
 
-    fn get_jpeg_paths(&self) -> Vec<std::path::PathBuf> {
-        self.pairs.iter().map(|p| p.jpeg_path.clone()).collect()
+    fn analysis(&self, idx: usize) -> Option<&Analysis> {
+        self.analyzer.get(self.pairs[idx].display_path())
+    }
+
+    fn passes_filter(&self, idx: usize) -> bool {
+        let analysis = self.analysis(idx);
+        match self.filter {
+            PhotoFilter::All => true,
+            PhotoFilter::Soft => analysis.is_some_and(|a| a.is_soft(self.soft_threshold)),
+            PhotoFilter::Overexposed => {
+                analysis.is_some_and(|a| a.is_overexposed(self.clipping_threshold))
+            }
+            PhotoFilter::Underexposed => {
+                analysis.is_some_and(|a| a.is_underexposed(self.clipping_threshold))
+            }
+        }
+    }
+
+    /// Short warnings for a pair's analysis under the current thresholds.
+    fn warnings(&self, idx: usize) -> Vec<&'static str> {
+        let Some(analysis) = self.analysis(idx) else {
+            return Vec::new();
+        };
+        [
+            (analysis.is_soft(self.soft_threshold), "Soft"),
+            (analysis.is_overexposed(self.clipping_threshold), "Blown"),
+            (analysis.is_underexposed(self.clipping_threshold), "Dark"),
+        ]
+        .into_iter()
+        .filter(|&(flagged, _)| flagged)
+        .map(|(_, warning)| warning)
+        .collect()
+    }
+
+    /// Limits navigation to `filter`, moving off the current photo if it
+    /// no longer passes.
+    fn set_filter(&mut self, filter: PhotoFilter) {
+        self.filter = filter;
+        self.step(0);
+    }
+
+    /// Pairs of a burst, which are always consecutive.
+    fn burst_members(&self, burst: u32) -> std::ops::Range<usize> {
+        let start = self
+            .pairs
+            .iter()
+            .position(|p| p.burst == Some(burst))
+            .unwrap_or(0);
+        let len = self.pairs[start..]
+            .iter()
+            .take_while(|p| p.burst == Some(burst))
+            .count();
+        start..start + len
+    }
+
+    /// Expands or collapses the current photo's burst. Collapsing moves to
+    /// the frame that stands for the whole stack.
+    fn toggle_current_burst(&mut self) {
+        let Some(burst) = self.current_pair().and_then(|p| p.burst) else {
+            return;
+        };
+        if self.expanded_bursts.remove(&burst) {
+            self.step(0);
+        } else {
+            self.expanded_bursts.insert(burst);
+        }
+    }
+
+    /// Keeps the current frame of its burst and marks the other frames for
+    /// deletion.
+    fn keep_current_in_burst(&mut self) {
+        let Some(burst) = self.current_pair().and_then(|p| p.burst) else {
+            return;
+        };
+        let members: Vec<usize> = self.burst_members(burst).collect();
+        for &idx in &members {
+            self.pairs[idx].action = if idx == self.current_index {
+                DeletionAction::KeepBoth
+            } else {
+                DeletionAction::DeleteBoth
+            };
+        }
+        self.pairs_changed(&members);
+    }
+
+    /// What to show for a pair: the developed RAW when RAW rendering is on
+    /// and supported for its format, the JPEG or camera preview otherwise.
+    /// Falls back to the preview once developing the RAW has failed, e.g.
+    /// for compressed RAFs.
+    fn image_key(&self, pair: &PhotoPair) -> ImageKey {
+        match pair.raw_path.as_deref() {
+            Some(raw) if self.show_raw && viewer::supports_raw_rendering(raw) => {
+                let key = ImageKey::new(raw, RenderMode::Raw);
+                if !self.image_cache.is_failed(&key) {
+                    return key;
+                }
+            }
+            _ => {}
+        }
+        ImageKey::new(pair.display_path(), RenderMode::Preview)
+    }
+
+    /// Keeps the images of the photos on screen cached: the current photo,
+    /// or every compare candidate, with their peaking maps.
+    fn pin_on_screen(&mut self) {
+        let on_screen = if self.compare.is_empty() {
+            vec![self.current_index]
+        } else {
+            self.compare.clone()
+        };
+        let mut pinned = Vec::new();
+        for pair in on_screen.into_iter().filter_map(|idx| self.pairs.get(idx)) {
+            let key = self.image_key(pair);
+            if self.show_peaking {
+                pinned.push(ImageKey::new(&key.path, RenderMode::Peaking));
+            }
+            pinned.push(key);
+        }
+        self.image_cache.set_pinned(pinned);
+    }
+
+    fn get_display_keys(&self, indices: &[usize]) -> Vec<ImageKey> {
+        indices.iter().map(|&idx| self.image_key(&self.pairs[idx])).collect()
+    }
+
+    /// Thumbnails of every pair with action and RAW badges. Only the visible
+    /// part of the strip is laid out and has thumbnails requested; clicking a
+    /// thumbnail jumps to it.
+    fn show_filmstrip(&mut self, ui: &mut egui::Ui, visible: &[usize]) {
+        let count = visible.len();
+        let current = self.visible_position(visible);
+        let mut scroll_area = egui::ScrollArea::horizontal().auto_shrink([false, true]);
+        // Keep the current photo in view as it changes
+        if self.filmstrip_index != Some(self.current_index) {
+            self.filmstrip_index = Some(self.current_index);
+            let offset =
+                (current as f32 + 0.5) * FILMSTRIP_ITEM_WIDTH - ui.available_width() / 2.0;
+            scroll_area = scroll_area.horizontal_scroll_offset(offset.max(0.0));
+        }
+
+        let mut clicked = None;
+        scroll_area.show_viewport(ui, |ui, viewport| {
+            let cell_size = egui::vec2(FILMSTRIP_ITEM_WIDTH, FILMSTRIP_THUMB_HEIGHT + 8.0);
+            ui.set_min_size(egui::vec2(count as f32 * FILMSTRIP_ITEM_WIDTH, cell_size.y));
+            let first = ((viewport.min.x / FILMSTRIP_ITEM_WIDTH).floor() as usize).min(count);
+            let last = ((viewport.max.x / FILMSTRIP_ITEM_WIDTH).ceil() as usize).min(count);
+
+            // Visible thumbnails first, then those just off either edge
+            let ahead = last..(last + FILMSTRIP_LOOKAHEAD).min(count);
+            let behind = first.saturating_sub(FILMSTRIP_LOOKAHEAD)..first;
+            let wanted = (first..last)
+                .chain(ahead)
+                .chain(behind.rev())
+                .map(|pos| self.pairs[visible[pos]].display_path().to_path_buf())
+                .collect();
+            self.image_cache.request_thumbnails(wanted);
+
+            let origin = ui.max_rect().min;
+            let mut missing = false;
+            for (pos, &idx) in visible.iter().enumerate().take(last).skip(first) {
+                let cell = egui::Rect::from_min_size(
+                    origin + egui::vec2(pos as f32 * FILMSTRIP_ITEM_WIDTH, 0.0),
+                    cell_size,
+                );
+                let response = ui.interact(cell, ui.id().with(idx), egui::Sense::click());
+                if response.clicked() {
+                    clicked = Some(idx);
+                }
+
+                missing |= self.paint_thumbnail(ui, idx, cell.shrink(4.0));
+                let painter = ui.painter();
+                if pos == current {
+                    painter.rect_stroke(
+                        cell.shrink(1.0),
+                        2.0,
+                        egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                    );
+                } else if response.hovered() {
+                    painter.rect_stroke(
+                        cell.shrink(1.0),
+                        2.0,
+                        ui.visuals().widgets.hovered.bg_stroke,
+                    );
+                }
+            }
+            if missing {
+                ui.ctx().request_repaint();
+            }
+        });
+
+        if let Some(idx) = clicked {
+            self.current_index = idx;
+        }
+    }
+
+    /// Contact sheet of every pair. Only visible rows are laid out and have
+    /// thumbnails requested. Click, Ctrl-click, Shift-click and dragging a
+    /// rubber band over the background select; double-clicking opens the
+    /// photo on its own.
+    fn show_grid_view(&mut self, ui: &mut egui::Ui, visible: &[usize]) {
+        let count = visible.len();
+        let current = self.visible_position(visible);
+        let columns = ((ui.available_width() / GRID_CELL_WIDTH).floor() as usize).max(1);
+        self.grid_columns = columns;
+        let rows = count.div_ceil(columns);
+        let cell_size = egui::vec2(GRID_CELL_WIDTH, GRID_CELL_HEIGHT);
+        let scroll_to_current = self.grid_index != Some(self.current_index);
+        self.grid_index = Some(self.current_index);
+
+        let mut clicked = None;
+        let mut opened = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .drag_to_scroll(false)
+            .show_viewport(ui, |ui, viewport| {
+                let content_size =
+                    egui::vec2(columns as f32 * GRID_CELL_WIDTH, rows as f32 * GRID_CELL_HEIGHT);
+                ui.set_min_size(content_size);
+                let origin = ui.max_rect().min;
+                let cell_rect = |pos: usize| {
+                    let offset = egui::vec2(
+                        (pos % columns) as f32 * GRID_CELL_WIDTH,
+                        (pos / columns) as f32 * GRID_CELL_HEIGHT,
+                    );
+                    egui::Rect::from_min_size(origin + offset, cell_size)
+                };
+                if scroll_to_current {
+                    ui.scroll_to_rect(cell_rect(current), None);
+                }
+
+                // The background takes drags for the rubber band; cells on top
+                // only take clicks
+                let background = ui.interact(
+                    egui::Rect::from_min_size(origin, content_size.max(viewport.size())),
+                    ui.id().with("grid_background"),
+                    egui::Sense::click_and_drag(),
+                );
+                if background.clicked() {
+                    self.clear_selection();
+                }
+                if background.drag_started()
+                    && let Some(pos) = background.interact_pointer_pos()
+                {
+                    let base = if ui.input(|i| i.modifiers.command) {
+                        self.selection.clone()
+                    } else {
+                        BTreeSet::new()
+                    };
+                    self.rubber_band = Some((pos - origin.to_vec2(), base));
+                }
+                if !background.dragged() && !background.drag_stopped() {
+                    self.rubber_band = None;
+                }
+                let mut band = None;
+                if let Some((start, base)) = &self.rubber_band
+                    && let Some(pos) = ui.ctx().pointer_latest_pos()
+                {
+                    let rect = egui::Rect::from_two_pos(*start + origin.to_vec2(), pos);
+                    let mut selection = base.clone();
+                    selection.extend(
+                        (0..count)
+                            .filter(|&pos| cell_rect(pos).intersects(rect))
+                            .map(|pos| visible[pos]),
+                    );
+                    self.selection = selection;
+                    band = Some(rect);
+                }
+
+                let first_row = (viewport.min.y / GRID_CELL_HEIGHT).floor() as usize;
+                let last_row = ((viewport.max.y / GRID_CELL_HEIGHT).ceil() as usize).min(rows);
+                let first = (first_row * columns).min(count);
+                let last = (last_row * columns).min(count);
+
+                // Visible thumbnails first, then rows just off either edge
+                let lookahead = GRID_LOOKAHEAD_ROWS * columns;
+                let ahead = last..(last + lookahead).min(count);
+                let behind = first.saturating_sub(lookahead)..first;
+                let wanted = (first..last)
+                    .chain(ahead)
+                    .chain(behind.rev())
+                    .map(|pos| self.pairs[visible[pos]].display_path().to_path_buf())
+                    .collect();
+                self.image_cache.request_thumbnails(wanted);
+
+                let mut missing = false;
+                for (pos, &idx) in visible.iter().enumerate().take(last).skip(first) {
+                    let cell = cell_rect(pos);
+                    let response = ui.interact(cell, ui.id().with(idx), egui::Sense::click());
+                    if response.double_clicked() {
+                        opened = Some(idx);
+                    } else if response.clicked() {
+                        clicked = Some((idx, ui.input(|i| i.modifiers)));
+                    }
+
+                    if self.selection.contains(&idx) {
+                        ui.painter()
+                            .rect_filled(cell.shrink(2.0), 4.0, ui.visuals().selection.bg_fill);
+                    }
+                    missing |= self.paint_thumbnail(ui, idx, cell.shrink(8.0));
+                    if pos == current {
+                        ui.painter().rect_stroke(
+                            cell.shrink(2.0),
+                            4.0,
+                            egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                        );
+                    } else if response.hovered() {
+                        ui.painter().rect_stroke(
+                            cell.shrink(2.0),
+                            4.0,
+                            ui.visuals().widgets.hovered.bg_stroke,
+                        );
+                    }
+                }
+                if let Some(band) = band {
+                    ui.painter().rect(
+                        band,
+                        0.0,
+                        ui.visuals().selection.bg_fill.gamma_multiply(0.3),
+                        ui.visuals().selection.stroke,
+                    );
+                }
+                if missing {
+                    ui.ctx().request_repaint();
+                }
+            });
+
+        if let Some((idx, modifiers)) = clicked {
+            self.grid_clicked(idx, modifiers, visible);
+        }
+        if let Some(idx) = opened {
+            self.select_only(idx);
+            self.show_grid = false;
+        }
+    }
+
+    /// The compare candidates side by side, or two by two for four, sharing
+    /// one zoom and position. Each has its own action selector.
+    fn show_compare_view(&mut self, ui: &mut egui::Ui) {
+        let candidates = self.compare.clone();
+        let area = ui.available_rect_before_wrap();
+        ui.allocate_rect(area, egui::Sense::hover());
+        let (columns, rows) = if candidates.len() == 4 {
+            (2, 2)
+        } else {
+            (candidates.len(), 1)
+        };
+        let cell_size = egui::vec2(area.width() / columns as f32, area.height() / rows as f32);
+
+        for (slot, &idx) in candidates.iter().enumerate() {
+            let offset = egui::vec2(
+                (slot % columns) as f32 * cell_size.x,
+                (slot / columns) as f32 * cell_size.y,
+            );
+            let cell = egui::Rect::from_min_size(area.min + offset, cell_size).shrink(4.0);
+            let (header, image_rect) = cell.split_top_bottom_at_y(cell.top() + COMPARE_HEADER_HEIGHT);
+
+            let mut header_ui = ui.new_child(
+                egui::UiBuilder::new()
+                    .max_rect(header)
+                    .layout(egui::Layout::left_to_right(egui::Align::Center)),
+            );
+            let pair = &self.pairs[idx];
+            header_ui.label(format!("Shift+{}: {}", slot + 1, pair.basename));
+            let mut chosen = None;
+            for action in [
+                DeletionAction::KeepBoth,
+                DeletionAction::DeleteRaw,
+                DeletionAction::DeleteJpeg,
+                DeletionAction::DeleteBoth,
+            ] {
+                let (letter, color) = action_badge(action);
+                if header_ui
+                    .selectable_label(pair.action == action, egui::RichText::new(letter).color(color))
+                    .on_hover_text(action.label())
+                    .clicked()
+                {
+                    chosen = Some(action);
+                }
+            }
+            if let Some(action) = chosen {
+                self.pairs[idx].action = action;
+                self.pairs_changed(&[idx]);
+            }
+
+            let key = self.image_key(&self.pairs[idx]);
+            self.image_cache.request_load(&key, 0);
+            let response = ui.interact(
+                image_rect,
+                ui.id().with(("compare", slot)),
+                egui::Sense::click_and_drag(),
+            );
+            let mut image_ui = ui.new_child(egui::UiBuilder::new().max_rect(image_rect));
+            image_ui.set_clip_rect(image_rect);
+            let rotation = self.pairs[idx].rotation;
+            match self.image_cache.get_texture(ui.ctx(), &key).cloned() {
+                Some(display) => {
+                    self.show_image(&mut image_ui, &response, image_rect, &display, &key, rotation);
+                }
+                None => {
+                    let text = if self.image_cache.is_failed(&key) {
+                        "Could not decode this image"
+                    } else {
+                        ui.ctx().request_repaint();
+                        "Loading..."
+                    };
+                    ui.painter().text(
+                        image_rect.center(),
+                        egui::Align2::CENTER_CENTER,
+                        text,
+                        egui::FontId::proportional(14.0),
+                        ui.visuals().text_color(),
+                    );
+                }
+            }
+
+            if idx == self.current_index {
+                ui.painter().rect_stroke(
+                    cell,
+                    2.0,
+                    egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                );
+            }
+        }
+    }
+
+    /// Groups of duplicates, one row each, with the photo each group keeps
+    /// highlighted. Clicking a photo keeps it instead; double-clicking opens
+    /// it on its own.
+    fn show_duplicates_view(&mut self, ui: &mut egui::Ui) {
+        let mut rejected = None;
+        let mut opened = None;
+        ui.horizontal(|ui| {
+            let mut heading = format!("{} groups of duplicates", self.duplicate_groups.len());
+            let pending = self.analyzer.pending_count();
+            if pending > 0 {
+                heading += &format!(" ({} photos not analysed yet)", pending);
+            }
+            ui.heading(heading);
+            if ui.button("Search Again").clicked() {
+                self.find_duplicates();
+            }
+            if ui.button("Reject Extras in All Groups").clicked() {
+                self.show_reject_duplicates_dialog = true;
+            }
+            if ui.button("Close (Esc)").clicked() {
+                self.show_duplicates = false;
+            }
+        });
+        ui.label("Click the photo to keep in each group; the others are marked Delete Both.");
+        ui.separator();
+
+        let wanted = self
+            .duplicate_groups
+            .iter()
+            .flat_map(|group| &group.members)
+            .map(|&idx| self.pairs[idx].display_path().to_path_buf())
+            .collect();
+        self.image_cache.request_thumbnails(wanted);
+
+        let mut missing = false;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                for group_idx in 0..self.duplicate_groups.len() {
+                    let members = self.duplicate_groups[group_idx].members.clone();
+                    ui.horizontal(|ui| {
+                        ui.strong(format!("Group {}: {} photos", group_idx + 1, members.len()));
+                        if ui.button("Keep Selected, Reject Others").clicked() {
+                            rejected = Some(vec![group_idx]);
+                        }
+                    });
+                    ui.horizontal_wrapped(|ui| {
+                        for &idx in &members {
+                            let (cell, response) = ui.allocate_exact_size(
+                                egui::vec2(GRID_CELL_WIDTH, GRID_CELL_HEIGHT),
+                                egui::Sense::click(),
+                            );
+                            if response.double_clicked() {
+                                opened = Some(idx);
+                            } else if response.clicked() {
+                                self.duplicate_groups[group_idx].keeper = idx;
+                            }
+
+                            if self.duplicate_groups[group_idx].keeper == idx {
+                                ui.painter().rect_filled(
+                                    cell.shrink(2.0),
+                                    4.0,
+                                    ui.visuals().selection.bg_fill,
+                                );
+                            } else if response.hovered() {
+                                ui.painter().rect_stroke(
+                                    cell.shrink(2.0),
+                                    4.0,
+                                    ui.visuals().widgets.hovered.bg_stroke,
+                                );
+                            }
+                            let (frame, caption) =
+                                cell.shrink(8.0).split_top_bottom_at_y(cell.bottom() - 24.0);
+                            missing |= self.paint_thumbnail(ui, idx, frame);
+                            ui.painter().with_clip_rect(caption).text(
+                                caption.center(),
+                                egui::Align2::CENTER_CENTER,
+                                self.pairs[idx].relative_display_path().display(),
+                                egui::FontId::proportional(11.0),
+                                ui.visuals().text_color(),
+                            );
+                        }
+                    });
+                    ui.separator();
+                }
+            });
+        if missing {
+            ui.ctx().request_repaint();
+        }
+
+        if let Some(groups) = rejected {
+            self.reject_duplicates(&groups);
+        }
+        if let Some(idx) = opened {
+            self.current_index = idx;
+            self.show_duplicates = false;
+        }
+    }
+
+    /// Paints a pair's thumbnail fitted into `frame`, with its action badge
+    /// in the top-left corner, a frame count for collapsed bursts in the
+    /// top-right, analysis warnings in the bottom-left and a RAW badge in the
+    /// bottom-right. Returns true if the thumbnail is still loading.
+    fn paint_thumbnail(&mut self, ui: &egui::Ui, idx: usize, frame: egui::Rect) -> bool {
+        let pair = &self.pairs[idx];
+        let painter = ui.painter();
+        let mut loading = false;
+        match self.image_cache.get_thumbnail(ui.ctx(), pair.display_path()) {
+            Some(texture) => {
+                let mut size = texture.size_vec2();
+                if pair.rotation % 2 == 1 {
+                    size = egui::vec2(size.y, size.x);
+                }
+                let scale = (frame.width() / size.x).min(frame.height() / size.y);
+                let image_size = texture.size_vec2() * scale;
+                // Painted unrotated around the centre, then turned
+                let image_rect = egui::Rect::from_center_size(frame.center(), image_size);
+                let angle = pair.rotation as f32 * std::f32::consts::FRAC_PI_2;
+                egui::Image::new((texture.id(), image_size))
+                    .rotate(angle, egui::Vec2::splat(0.5))
+                    .paint_at(ui, image_rect);
+            }
+            None => {
+                painter.rect_filled(frame, 2.0, ui.visuals().faint_bg_color);
+                let key = ImageKey::new(pair.display_path(), RenderMode::Thumbnail);
+                loading = !self.image_cache.is_failed(&key);
+            }
+        }
+
+        let (letter, color) = action_badge(pair.action);
+        let badge = egui::Rect::from_min_size(frame.min, egui::vec2(16.0, 16.0));
+        painter.rect_filled(badge, 2.0, color);
+        painter.text(
+            badge.center(),
+            egui::Align2::CENTER_CENTER,
+            letter,
+            egui::FontId::proportional(11.0),
+            egui::Color32::BLACK,
+        );
+        if let Some(burst) = pair.burst {
+            if self.expanded_bursts.contains(&burst) {
+                // Underline every frame of an open stack
+                let line = egui::Rect::from_min_max(
+                    egui::pos2(frame.left(), frame.bottom() - 3.0),
+                    frame.right_bottom(),
+                );
+                painter.rect_filled(line, 0.0, ui.visuals().selection.stroke.color);
+            } else {
+                let frames = self.pairs.iter().filter(|p| p.burst == Some(burst)).count();
+                let badge = egui::Rect::from_min_size(
+                    frame.right_top() - egui::vec2(28.0, 0.0),
+                    egui::vec2(28.0, 16.0),
+                );
+                painter.rect_filled(badge, 2.0, egui::Color32::from_black_alpha(180));
+                painter.text(
+                    badge.center(),
+                    egui::Align2::CENTER_CENTER,
+                    format!("×{}", frames),
+                    egui::FontId::proportional(11.0),
+                    egui::Color32::WHITE,
+                );
+            }
+        }
+        for (slot, warning) in self.warnings(idx).into_iter().enumerate() {
+            let badge = egui::Rect::from_min_size(
+                frame.left_bottom() + egui::vec2(slot as f32 * 34.0, -16.0),
+                egui::vec2(32.0, 16.0),
+            );
+            painter.rect_filled(badge, 2.0, WARNING_COLOR);
+            painter.text(
+                badge.center(),
+                egui::Align2::CENTER_CENTER,
+                warning,
+                egui::FontId::proportional(10.0),
+                egui::Color32::BLACK,
+            );
+        }
+        if pair.has_raw() {
+            painter.text(
+                frame.right_bottom() - egui::vec2(2.0, 2.0),
+                egui::Align2::RIGHT_BOTTOM,
+                "RAW",
+                egui::FontId::proportional(10.0),
+                egui::Color32::WHITE,
+            );
+        }
+        loading
+    }
+
+    /// Paints the current photo, fitted or zoomed, and handles zooming and
+    /// panning. Clicking or Z toggles 1:1 at the pointer, the wheel zooms
+    /// about the pointer and dragging pans. When zoomed past the display
+    /// texture's resolution the visible region is shown from a
+    /// full-resolution decode. Clipped areas and in-focus edges are painted
+    /// on top when their overlays are on.
+    fn show_image(
+        &mut self,
+        ui: &mut egui::Ui,
+        response: &egui::Response,
+        rect: egui::Rect,
+        display: &viewer::DisplayTexture,
+        key: &ImageKey,
+        rotation: u8,
+    ) {
+        let ppp = ui.ctx().pixels_per_point();
+        let image_size = display.source_size;
+        let texture_scale = display.texture.size_vec2().x / image_size.x;
+        let fit = ImageLayout::fit(rect, image_size, rotation, texture_scale);
+        let layout = match self.zoom {
+            Some(view) => ImageLayout::zoomed(rect, image_size, rotation, view, ppp),
+            None => fit,
+        };
+
+        let pointer = response.hover_pos().unwrap_or(rect.center());
+        // When comparing, Z zooms the photo under the pointer
+        let toggle_key = ui.input(|i| i.key_pressed(egui::Key::Z) && !i.modifiers.command)
+            && (self.compare.is_empty() || response.hovered());
+        if response.clicked() || toggle_key {
+            self.zoom = match self.zoom {
+                Some(_) => None,
+                None => Some(fit.zoom_about(pointer, 1.0, ppp)),
+            };
+        } else if response.dragged() && self.zoom.is_some() {
+            self.zoom = Some(layout.panned(response.drag_delta(), ppp));
+        } else if response.hovered() {
+            let factor = ui.input(|i| i.zoom_delta() * (i.smooth_scroll_delta.y / 200.0).exp());
+            if factor != 1.0 {
+                let zoom = (layout.zoom(ppp) * factor).min(zoom::MAX_ZOOM);
+                self.zoom = if zoom <= fit.zoom(ppp) {
+                    None
+                } else {
+                    Some(layout.zoom_about(pointer, zoom, ppp))
+                };
+            }
+        }
+
+        let layout = match self.zoom {
+            Some(view) => ImageLayout::zoomed(rect, image_size, rotation, view, ppp),
+            None => fit,
+        };
+        let whole = egui::Rect::from_min_size(egui::Pos2::ZERO, image_size);
+        layout.paint(ui, display.texture.id(), whole);
+
+        // The display texture is enough until it would be magnified; RAW
+        // renders are only developed at display size
+        let region = layout.visible_region();
+        let magnified = layout.scale > texture_scale;
+        let fits_in_tile =
+            region.width() <= viewer::MAX_TILE_SIZE && region.height() <= viewer::MAX_TILE_SIZE;
+        if magnified && fits_in_tile && key.mode == RenderMode::Preview {
+            // The full decode is only needed until a tile covering the view
+            // has been cut from it
+            let full_key = ImageKey::new(&key.path, RenderMode::Full);
+            match self.image_cache.get_tile(ui.ctx(), &full_key, region) {
+                Some((tile, tile_region)) => layout.paint(ui, tile.id(), tile_region),
+                None if !self.image_cache.is_failed(&full_key) => {
+                    self.image_cache.request_load(&full_key, 0);
+                    ui.ctx().request_repaint();
+                }
+                None => {}
+            }
+        }
+
+        if self.show_clipping
+            && let Some(overlay) = self.image_cache.get_clipping_overlay(ui.ctx(), key)
+        {
+            layout.paint(ui, overlay.id(), whole);
+        }
+
+        // Peaking is found from the full-resolution decode, after the photo
+        // itself but ahead of its neighbours
+        if self.show_peaking {
+            let peaking_key = ImageKey::new(&key.path, RenderMode::Peaking);
+            self.image_cache.request_load(&peaking_key, 1);
+            match self.image_cache.get_peaking_overlay(
+                ui.ctx(),
+                &peaking_key,
+                self.peaking_threshold,
+                self.peaking_color,
+            ) {
+                Some(overlay) => layout.paint(ui, overlay.id(), whole),
+                None if !self.image_cache.is_failed(&peaking_key) => ui.ctx().request_repaint(),
+                None => {}
+            }
+        }
+    }
+
+    /// Pairs that edits apply to: the grid selection, or the current pair.
+    fn edit_targets(&self) -> Vec<usize> {
+        if self.show_grid && !self.selection.is_empty() {
+            self.selection.iter().copied().filter(|&idx| idx < self.pairs.len()).collect()
+        } else if self.current_index < self.pairs.len() {
+            vec![self.current_index]
+        } else {
+            Vec::new()
+        }
     }
 
     fn set_action(&mut self, action: DeletionAction) {
+        let targets = self.edit_targets();
+        for &idx in &targets {
+            self.pairs[idx].action = action;
+        }
+        self.pairs_changed(&targets);
+    }
+
+    fn set_rating(&mut self, rating: u8) {
+        let targets = self.edit_targets();
+        for &idx in &targets {
+            self.pairs[idx].rating = rating.min(MAX_RATING);
+        }
+        self.pairs_changed(&targets);
+    }
+
+    /// Applies `label`, or clears it if every targeted photo already has it.
+    fn toggle_color_label(&mut self, label: ColorLabel) {
+        let targets = self.edit_targets();
+        let all_labelled = targets
+            .iter()
+            .all(|&idx| self.pairs[idx].color_label == Some(label));
+        for &idx in &targets {
+            self.pairs[idx].color_label = if all_labelled { None } else { Some(label) };
+        }
+        self.pairs_changed(&targets);
+    }
+
+    /// Records edits to pairs in the session and their XMP sidecars.
+    fn pairs_changed(&mut self, indices: &[usize]) {
+        if indices.is_empty() {
+            return;
+        }
+        self.session_dirty = true;
+        for &idx in indices {
+            let pair = &self.pairs[idx];
+            if let Err(e) = xmp::sync_sidecar(pair) {
+                eprintln!("Failed to write XMP sidecar for {}: {}", pair.key(), e);
+                self.status_message = Some(format!("Failed to write XMP sidecar: {}", e));
+            }
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.selection.clear();
+        self.selection_anchor = None;
+        self.rubber_band = None;
+    }
+
+    /// Selects only `idx` and makes it the current photo.
+    fn select_only(&mut self, idx: usize) {
+        self.selection = BTreeSet::from([idx]);
+        self.selection_anchor = Some(idx);
+        self.current_index = idx;
+    }
+
+    /// Groups photos that look the same and shows them for review. Only
+    /// photos analysed so far are compared.
+    fn find_duplicates(&mut self) {
+        let photos: Vec<(usize, u64, f32)> = (0..self.pairs.len())
+            .filter_map(|idx| {
+                let analysis = self.analysis(idx)?;
+                Some((idx, analysis.hash, analysis.sharpness))
+            })
+            .collect();
+        self.duplicate_groups = duplicates::find_duplicates(&self.pairs, &photos);
+        self.show_duplicates = true;
+        self.show_reject_duplicates_dialog = false;
+        self.show_grid = false;
+        self.compare.clear();
+    }
+
+    /// Marks every photo of the given duplicate groups except the one each
+    /// group keeps to delete both files.
+    fn reject_duplicates(&mut self, groups: &[usize]) {
+        let mut rejected = Vec::new();
+        for &group_idx in groups {
+            let group = &self.duplicate_groups[group_idx];
+            for &idx in &group.members {
+                if idx != group.keeper {
+                    self.pairs[idx].action = DeletionAction::DeleteBoth;
+                    rejected.push(idx);
+                }
+            }
+        }
+        self.pairs_changed(&rejected);
+    }
+
+    /// Compares the grid selection, or the current photo and the next one
+    /// outside the grid.
+    fn start_compare(&mut self) {
+        let candidates: Vec<usize> = if self.show_grid && self.selection.len() >= 2 {
+            self.selection.iter().copied().take(MAX_COMPARE).collect()
+        } else {
+            (self.current_index..(self.current_index + 2).min(self.pairs.len())).collect()
+        };
+        if candidates.len() < 2 {
+            self.status_message = Some("Select 2 to 4 photos to compare".to_string());
+            return;
+        }
+        self.current_index = candidates[0];
+        self.compare = candidates;
+        self.show_grid = false;
+    }
+
+    /// Keeps the compare candidate in `slot` and marks the others for
+    /// deletion.
+    fn promote(&mut self, slot: usize) {
+        let Some(&winner) = self.compare.get(slot) else {
+            return;
+        };
+        let candidates = self.compare.clone();
+        for &idx in &candidates {
+            self.pairs[idx].action = if idx == winner {
+                DeletionAction::KeepBoth
+            } else {
+                DeletionAction::DeleteBoth
+            };
+        }
+        self.pairs_changed(&candidates);
+        self.current_index = winner;
+    }
+
+    /// Handles a click on a grid cell: plain clicks select one photo,
+    /// Ctrl toggles it and Shift extends from the last clicked photo.
+    fn grid_clicked(&mut self, idx: usize, modifiers: egui::Modifiers, visible: &[usize]) {
+        if modifiers.shift {
+            let anchor = self.selection_anchor.unwrap_or(self.current_index);
+            if !modifiers.command {
+                self.selection.clear();
+            }
+            // The range runs in grid order, which follows the sort
+            let position = |target| visible.iter().position(|&i| i == target);
+            match (position(anchor), position(idx)) {
+                (Some(a), Some(b)) => self.selection.extend(&visible[a.min(b)..=a.max(b)]),
+                _ => {
+                    self.selection.insert(idx);
+                }
+            }
+            self.current_index = idx;
+        } else if modifiers.command {
+            if !self.selection.remove(&idx) {
+                self.selection.insert(idx);
+            }
+            self.selection_anchor = Some(idx);
+            self.current_index = idx;
+        } else {
+            self.select_only(idx);
+        }
+    }
+
+    fn rotate_current(&mut self, quarter_turns: i8) {
         if let Some(pair) = self.pairs.get_mut(self.current_index) {
-            pair.action = action;
+            pair.rotate(quarter_turns);
+            self.session_dirty = true;
         }
     }
 
@@ -85,39 +1387,149 @@ impl PhotoCullerApp {
 
 impl eframe::App for PhotoCullerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Handle keyboard input
+        // Handle keyboard input. Ratings are consumed first so that Ctrl+1
+        // doesn't also trigger the plain 1 binding below. Keys that move or
+        // mark the current photo are ignored while reviewing duplicates,
+        // which hide it.
+        let photo_keys = !self.show_duplicates;
+        let rating = ctx.input_mut(|i| {
+            RATING_KEYS
+                .iter()
+                .position(|&key| i.consume_key(egui::Modifiers::CTRL, key))
+        });
+        if let Some(rating) = rating
+            && photo_keys
+        {
+            self.set_rating(rating as u8);
+        }
+        let promoted = ctx.input_mut(|i| {
+            PROMOTE_KEYS
+                .iter()
+                .take(self.compare.len())
+                .position(|&key| i.consume_key(egui::Modifiers::SHIFT, key))
+        });
+        if let Some(slot) = promoted
+            && photo_keys
+        {
+            self.promote(slot);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::K)) && photo_keys {
+            self.keep_current_in_burst();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::F)) {
+            self.find_duplicates();
+        }
+
+        let index_before_keys = self.current_index;
         ctx.input(|i| {
-            if i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::Space) {
+            if photo_keys && self.show_grid && i.key_pressed(egui::Key::ArrowDown) {
+                self.step(self.grid_columns.max(1) as isize);
+            }
+            if photo_keys && self.show_grid && i.key_pressed(egui::Key::ArrowUp) {
+                self.step(-(self.grid_columns.max(1) as isize));
+            }
+            if photo_keys && self.show_grid && i.key_pressed(egui::Key::Enter) {
+                self.show_grid = false;
+            }
+            if photo_keys && self.show_grid && i.modifiers.command && i.key_pressed(egui::Key::A) {
+                self.selection = (0..self.pairs.len())
+                    .filter(|&idx| self.passes_filter(idx))
+                    .collect();
+            }
+            if i.key_pressed(egui::Key::G) {
+                self.show_grid = !self.show_grid;
+            }
+            if photo_keys && i.key_pressed(egui::Key::C) && !i.modifiers.command {
+                if self.compare.is_empty() {
+                    self.start_compare();
+                } else {
+                    self.compare.clear();
+                }
+            }
+            if i.key_pressed(egui::Key::Escape) {
+                self.compare.clear();
+                self.show_duplicates = false;
+            }
+            if photo_keys
+                && (i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::Space))
+            {
                 self.next_image();
             }
-            if i.key_pressed(egui::Key::ArrowLeft) {
+            if photo_keys && i.key_pressed(egui::Key::ArrowLeft) {
                 self.prev_image();
             }
-            if i.key_pressed(egui::Key::Home) {
-                self.current_index = 0;
+            if photo_keys
+                && i.key_pressed(egui::Key::Home)
+                && let Some(&first) = self.visible_indices().first()
+            {
+                self.current_index = first;
             }
-            if i.key_pressed(egui::Key::End) && !self.pairs.is_empty() {
-                self.current_index = self.pairs.len() - 1;
+            if photo_keys
+                && i.key_pressed(egui::Key::End)
+                && let Some(&last) = self.visible_indices().last()
+            {
+                self.current_index = last;
             }
-            if i.key_pressed(egui::Key::Num1) || i.key_pressed(egui::Key::K) {
+            if photo_keys && i.key_pressed(egui::Key::S) {
+                self.toggle_current_burst();
+            }
+            if photo_keys && (i.key_pressed(egui::Key::Num1) || i.key_pressed(egui::Key::K)) {
                 self.set_action(DeletionAction::KeepBoth);
             }
-            if i.key_pressed(egui::Key::Num2) || i.key_pressed(egui::Key::R) {
+            if photo_keys && (i.key_pressed(egui::Key::Num2) || i.key_pressed(egui::Key::R)) {
                 self.set_action(DeletionAction::DeleteRaw);
             }
-            if i.key_pressed(egui::Key::Num3) || i.key_pressed(egui::Key::J) {
+            if photo_keys && (i.key_pressed(egui::Key::Num3) || i.key_pressed(egui::Key::J)) {
                 self.set_action(DeletionAction::DeleteJpeg);
             }
-            if i.key_pressed(egui::Key::Num4) || i.key_pressed(egui::Key::B) {
+            if photo_keys && (i.key_pressed(egui::Key::Num4) || i.key_pressed(egui::Key::B)) {
                 self.set_action(DeletionAction::DeleteBoth);
             }
+            for (key, label) in COLOR_LABEL_KEYS {
+                if photo_keys && i.key_pressed(key) {
+                    self.toggle_color_label(label);
+                }
+            }
+            if photo_keys && i.key_pressed(egui::Key::OpenBracket) {
+                self.rotate_current(-1);
+            }
+            if photo_keys && i.key_pressed(egui::Key::CloseBracket) {
+                self.rotate_current(1);
+            }
+            if i.key_pressed(egui::Key::I) {
+                self.show_info_panel = !self.show_info_panel;
+            }
+            if i.key_pressed(egui::Key::V) {
+                self.show_raw = !self.show_raw;
+            }
+            if i.key_pressed(egui::Key::O) && !i.modifiers.command {
+                self.show_clipping = !self.show_clipping;
+            }
+            if i.key_pressed(egui::Key::P) {
+                self.show_peaking = !self.show_peaking;
+            }
+            if i.key_pressed(egui::Key::L) {
+                self.zoom_locked = !self.zoom_locked;
+            }
+            if i.key_pressed(egui::Key::F) {
+                self.show_filmstrip = !self.show_filmstrip;
+            }
             if i.modifiers.ctrl && i.key_pressed(egui::Key::O) {
                 self.open_folder();
             }
             if i.modifiers.ctrl && i.key_pressed(egui::Key::D) {
                 self.show_delete_dialog = true;
             }
+            if i.modifiers.ctrl && i.key_pressed(egui::Key::Z) {
+                self.undo_last_delete();
+            }
         });
+        // Moving through the grid with the keyboard moves the selection too
+        if self.show_grid && self.current_index != index_before_keys {
+            self.select_only(self.current_index);
+        }
+        let visible = self.visible_indices();
+        self.pin_on_screen();
 
         // Top panel 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
@@ -131,11 +1543,150 @@ impl eframe::App for PhotoCullerApp {
                         self.show_delete_dialog = true;
                         ui.close_menu();
                     }
+                    if ui.button("Undo Last Delete (Ctrl+Z)").clicked() {
+                        self.undo_last_delete();
+                        ui.close_menu();
+                    }
+                    let find = egui::Button::new("Find Duplicates (Ctrl+F)");
+                    if ui.add_enabled(!self.pairs.is_empty(), find).clicked() {
+                        self.find_duplicates();
+                        ui.close_menu();
+                    }
+                    ui.add_enabled_ui(self.folder_path.is_some(), |ui| {
+                        let mut recursive = self.scan_settings.recursive;
+                        if ui.checkbox(&mut recursive, "Include Subfolders").changed() {
+                            self.set_recursive(recursive);
+                            ui.close_menu();
+                        }
+                        ui.menu_button("RAW Formats", |ui| {
+                            if ui
+                                .selectable_label(
+                                    self.scan_settings.raw_extensions.is_none(),
+                                    "Auto-detect",
+                                )
+                                .clicked()
+                            {
+                                self.set_raw_extensions(None);
+                            }
+                            ui.separator();
+                            for ext in scanner::KNOWN_RAW_EXTENSIONS {
+                                let enabled =
+                                    self.effective_raw_extensions().iter().any(|e| e == ext);
+                                if ui.selectable_label(enabled, ext.to_uppercase()).clicked() {
+                                    self.toggle_raw_extension(ext);
+                                }
+                            }
+                        });
+                    });
                     ui.separator();
                     if ui.button("Quit").clicked() {
+                        self.save_session();
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
+                ui.menu_button("View", |ui| {
+                    if ui.checkbox(&mut self.show_info_panel, "Shooting Info (I)").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.show_raw, "RAW Rendering (V)").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.show_clipping, "Clipping Overlay (O)").clicked() {
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Focus Peaking", |ui| {
+                        ui.checkbox(&mut self.show_peaking, "Show (P)");
+                        ui.add(
+                            egui::Slider::new(&mut self.peaking_threshold, 10..=200)
+                                .text("Threshold"),
+                        );
+                        ui.horizontal(|ui| {
+                            for (name, color) in PEAKING_COLORS {
+                                let text = egui::RichText::new(name).color(color);
+                                if ui.selectable_label(self.peaking_color == color, text).clicked() {
+                                    self.peaking_color = color;
+                                }
+                            }
+                        });
+                    });
+                    if ui.checkbox(&mut self.zoom_locked, "Lock Zoom (L)").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.show_filmstrip, "Filmstrip (F)").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.show_grid, "Grid (G)").clicked() {
+                        ui.close_menu();
+                    }
+                    let comparing = !self.compare.is_empty();
+                    if ui.selectable_label(comparing, "Compare (C)").clicked() {
+                        if comparing {
+                            self.compare.clear();
+                        } else {
+                            self.start_compare();
+                        }
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Sort", |ui| {
+                        for order in SortOrder::ALL {
+                            if ui.selectable_label(self.sort_order == order, order.label()).clicked()
+                            {
+                                self.sort_order = order;
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("Filter", |ui| {
+                        for filter in PhotoFilter::ALL {
+                            if ui.selectable_label(self.filter == filter, filter.label()).clicked() {
+                                self.set_filter(filter);
+                                ui.close_menu();
+                            }
+                        }
+                        ui.separator();
+                        ui.add(
+                            egui::Slider::new(&mut self.soft_threshold, 10.0..=1000.0)
+                                .logarithmic(true)
+                                .text("Soft below"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut self.clipping_threshold, 0.001..=0.5)
+                                .logarithmic(true)
+                                .custom_formatter(|v, _| format!("{:.1}%", v * 100.0))
+                                .custom_parser(|s| {
+                                    let percent = s.trim().trim_end_matches('%').parse::<f64>();
+                                    percent.ok().map(|p| p / 100.0)
+                                })
+                                .text("Clipped above"),
+                        );
+                    });
+                    ui.menu_button("Cache", |ui| {
+                        ui.label(format!(
+                            "In use: {} MB",
+                            self.image_cache.cached_bytes() / (1024 * 1024)
+                        ));
+                        ui.separator();
+                        let budget = self.image_cache.memory_budget_mb();
+                        for choice in MEMORY_BUDGET_CHOICES_MB {
+                            let text = if choice >= 1024 {
+                                format!("{} GB", choice / 1024)
+                            } else {
+                                format!("{} MB", choice)
+                            };
+                            if ui.selectable_label(budget == choice, text).clicked() {
+                                self.image_cache.set_memory_budget_mb(choice);
+                            }
+                        }
+                        ui.separator();
+                        let mut radius = self.image_cache.preload_radius();
+                        if ui
+                            .add(egui::Slider::new(&mut radius, 0..=10).text("Preload radius"))
+                            .changed()
+                        {
+                            self.image_cache.set_preload_radius(radius);
+                        }
+                    });
+                });
             });
         });
 
@@ -147,22 +1698,91 @@ impl eframe::App for PhotoCullerApp {
                         "Image {} of {} | {} | Action: {}",
                         self.current_index + 1,
                         self.pairs.len(),
-                        pair.jpeg_path.file_name().unwrap_or_default().to_string_lossy(),
+                        pair.relative_display_path().display(),
                         pair.action.label()
                     ));
+                    if self.show_grid && !self.selection.is_empty() {
+                        ui.label(format!(" | {} selected", self.selection.len()));
+                    }
                     if pair.has_raw() {
                         ui.label(" | RAW: Yes");
                     } else {
                         ui.label(" | RAW: No");
                     }
+                    if !pair.has_jpeg() {
+                        ui.label(" | JPEG: No");
+                    }
+                    if self.show_raw {
+                        if self.image_key(pair).mode == RenderMode::Raw {
+                            ui.label(" | View: RAW render");
+                        } else {
+                            let reason = match pair.raw_path.as_deref() {
+                                Some(raw) if viewer::supports_raw_rendering(raw) => {
+                                    "compressed or unreadable RAF"
+                                }
+                                _ => "not supported for this format",
+                            };
+                            ui.colored_label(
+                                WARNING_COLOR,
+                                format!(
+                                    " | View: RAW render unavailable ({}), showing the preview",
+                                    reason
+                                ),
+                            );
+                        }
+                    }
+                    if let Some(view) = self.zoom {
+                        let lock = if self.zoom_locked { " (locked)" } else { "" };
+                        ui.label(format!(" | Zoom: {:.0}%{}", view.zoom * 100.0, lock));
+                    }
+                    if let Some(burst) = pair.burst {
+                        let members = self.burst_members(burst);
+                        ui.label(format!(
+                            " | Burst: {}/{}",
+                            self.current_index - members.start + 1,
+                            members.len()
+                        ));
+                    }
+                    if let Some(analysis) = self.analysis(self.current_index) {
+                        ui.label(format!(" | Sharpness: {:.0}", analysis.sharpness));
+                    }
+                    let warnings = self.warnings(self.current_index);
+                    if !warnings.is_empty() {
+                        ui.label(" |");
+                        ui.colored_label(WARNING_COLOR, warnings.join(", "));
+                    }
+                    ui.label(format!(" | Rating: {}/{}", pair.rating, MAX_RATING));
+                    if let Some(label) = pair.color_label {
+                        ui.label(" | Label:");
+                        ui.colored_label(label_color(label), label.label());
+                    }
                 } else if let Some(ref msg) = self.status_message {
                     ui.label(msg);
                 } else {
                     ui.label("Press Ctrl+O to open a folder");
                 }
+                if self.filter != PhotoFilter::All {
+                    ui.label(format!(
+                        " | Filter: {} ({})",
+                        self.filter.label(),
+                        visible.len()
+                    ));
+                }
+                let analysing = self.analyzer.pending_count();
+                if analysing > 0 {
+                    ui.label(format!(" | Analysing {} photos", analysing));
+                }
             });
         });
 
+        // Filmstrip above the status bar
+        if self.show_filmstrip && !self.show_grid && !self.show_duplicates && !self.pairs.is_empty()
+        {
+            egui::TopBottomPanel::bottom("filmstrip").show(ctx, |ui| {
+                self.show_filmstrip(ui, &visible);
+            });
+        }
+
         // Side panel with action buttons
         egui::SidePanel::right("actions_panel")
             .min_width(150.0)
@@ -209,6 +1829,59 @@ impl eframe::App for PhotoCullerApp {
                     self.set_action(DeletionAction::DeleteBoth);
                 }
 
+                ui.separator();
+                ui.heading("Rating");
+
+                let current_rating = self.current_pair().map(|p| p.rating);
+                ui.horizontal_wrapped(|ui| {
+                    for rating in 0..=MAX_RATING {
+                        if ui
+                            .selectable_label(current_rating == Some(rating), rating.to_string())
+                            .clicked()
+                        {
+                            self.set_rating(rating);
+                        }
+                    }
+                });
+                ui.label("Ctrl+0-5 : Set rating");
+
+                ui.separator();
+                ui.heading("Label");
+
+                let current_label = self.current_pair().and_then(|p| p.color_label);
+                for label in ColorLabel::ALL {
+                    let text = egui::RichText::new(label.label()).color(label_color(label));
+                    if ui
+                        .selectable_label(current_label == Some(label), text)
+                        .clicked()
+                    {
+                        self.toggle_color_label(label);
+                    }
+                }
+                ui.label("6-9 : Red/Yellow/Green/Blue");
+
+                if let Some(burst) = self.current_pair().and_then(|p| p.burst) {
+                    let members = self.burst_members(burst);
+                    ui.separator();
+                    ui.heading("Burst");
+                    ui.label(format!(
+                        "Frame {} of {}",
+                        self.current_index - members.start + 1,
+                        members.len()
+                    ));
+                    let text = if self.expanded_bursts.contains(&burst) {
+                        "S: Collapse Stack"
+                    } else {
+                        "S: Expand Stack"
+                    };
+                    if ui.button(text).clicked() {
+                        self.toggle_current_burst();
+                    }
+                    if ui.button("Shift+K: Keep This, Reject Rest").clicked() {
+                        self.keep_current_in_burst();
+                    }
+                }
+
                 ui.separator();
                 ui.heading("Navigation");
                 ui.label("< / > : Prev/Next");
@@ -216,34 +1889,153 @@ impl eframe::App for PhotoCullerApp {
                 ui.label("Home   : First");
                 ui.label("End    : Last");
                 ui.label("Ctrl+D : Delete Marked");
+                ui.label("Ctrl+Z : Undo Delete");
+                ui.label("[ / ]  : Rotate Left/Right");
+                ui.label("I      : Shooting Info");
+            });
+
+        // Shooting data for the current photo, next to the actions
+        egui::SidePanel::right("info_panel")
+            .min_width(180.0)
+            .show_animated(ctx, self.show_info_panel, |ui| {
+                ui.heading("Info");
+                ui.separator();
+
+                let Some(pair) = self.current_pair() else {
+                    return;
+                };
+                let key = self.image_key(pair);
+                let stats = self
+                    .image_cache
+                    .get_texture(ctx, &key)
+                    .and_then(|display| display.stats.clone());
+                if let Some(stats) = stats {
+                    stats.histogram.show(ui);
+                    ui.separator();
+                }
+
+                match self.analysis(self.current_index) {
+                    Some(analysis) => {
+                        let at = if analysis.at_focus_point {
+                            "at focus point"
+                        } else {
+                            "whole frame"
+                        };
+                        let threshold = self.clipping_threshold;
+                        let rows = [
+                            (
+                                "Sharpness",
+                                format!("{:.0} ({})", analysis.sharpness, at),
+                                analysis.is_soft(self.soft_threshold),
+                            ),
+                            (
+                                "Highlights",
+                                format!("{:.1}% clipped", analysis.clipped_highlights * 100.0),
+                                analysis.is_overexposed(threshold),
+                            ),
+                            (
+                                "Shadows",
+                                format!("{:.1}% clipped", analysis.clipped_shadows * 100.0),
+                                analysis.clipped_shadows > threshold,
+                            ),
+                            (
+                                "Mean level",
+                                format!("{:.0}%", analysis.mean_luma * 100.0),
+                                analysis.is_underexposed(threshold),
+                            ),
+                        ];
+                        egui::Grid::new("analysis_grid").num_columns(2).show(ui, |ui| {
+                            for (name, value, flagged) in rows {
+                                ui.label(name);
+                                if flagged {
+                                    ui.colored_label(WARNING_COLOR, value);
+                                } else {
+                                    ui.label(value);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    }
+                    None => {
+                        ui.label("Analysing...");
+                    }
+                }
+                ui.separator();
+
+                let Some(exif) = self.current_pair().and_then(|p| p.exif.as_ref()) else {
+                    ui.label("No EXIF data");
+                    return;
+                };
+                egui::Grid::new("exif_grid").num_columns(2).show(ui, |ui| {
+                    let rows = [
+                        ("Camera", exif.camera()),
+                        ("Lens", exif.lens.clone()),
+                        ("Shutter", exif.shutter_speed()),
+                        ("Aperture", exif.aperture()),
+                        ("ISO", exif.iso.map(|iso| iso.to_string())),
+                        ("Focal length", exif.focal()),
+                        ("Exp. comp.", exif.exposure_compensation()),
+                        ("Film sim.", exif.film_simulation.clone()),
+                        ("Taken", exif.date_time_original.clone()),
+                    ];
+                    for (name, value) in rows {
+                        if let Some(value) = value {
+                            ui.label(name);
+                            ui.label(value);
+                            ui.end_row();
+                        }
+                    }
+                });
             });
 
         // Central panel with image viewer
         egui::CentralPanel::default().show(ctx, |ui| {
             // Poll for completed background loads
             self.image_cache.poll();
+            self.analyzer.poll();
+            if self.analyzer.pending_count() > 0 {
+                ctx.request_repaint_after(std::time::Duration::from_millis(500));
+            }
 
             if self.pairs.is_empty() {
                 ui.centered_and_justified(|ui| {
                     ui.heading("No photos loaded. Press Ctrl+O to open a folder.");
                 });
+            } else if self.show_duplicates {
+                self.show_duplicates_view(ui);
+            } else if visible.is_empty() && self.compare.is_empty() {
+                // The current photo is left where it was, but it is filtered out
+                ui.centered_and_justified(|ui| {
+                    ui.heading(format!("No photos match the filter: {}", self.filter.label()));
+                });
+            } else if self.show_grid {
+                self.show_grid_view(ui, &visible);
+            } else if !self.compare.is_empty() {
+                self.show_compare_view(ui);
             } else if let Some(pair) = self.pairs.get(self.current_index).cloned() {
-                // Preload adjacent images for smooth navigation
-                let paths = self.get_jpeg_paths();
-                self.image_cache.preload_adjacent(&paths, self.current_index);
-
-                if let Some(texture) = self.image_cache.get_texture(ctx, &pair.jpeg_path) {
-                    let available_size = ui.available_size();
-                    let image_size = texture.size_vec2();
+                if self.zoom_index != self.current_index {
+                    self.zoom_index = self.current_index;
+                    if !self.zoom_locked {
+                        self.zoom = None;
+                    }
+                }
 
-                    // Calculate scaling to fit while maintaining aspect ratio
-                    let scale = (available_size.x / image_size.x)
-                        .min(available_size.y / image_size.y)
-                        .min(1.0);
-                    let display_size = image_size * scale;
+                // Preload adjacent images for smooth navigation, skipping
+                // frames hidden in collapsed bursts
+                let keys = self.get_display_keys(&visible);
+                self.image_cache
+                    .preload_adjacent(&keys, self.visible_position(&visible));
+                let key = &self.image_key(&pair);
+                // The current frame may itself be hidden, e.g. after a promote
+                self.image_cache.request_load(key, 0);
 
+                if let Some(display) = self.image_cache.get_texture(ctx, key).cloned() {
+                    let (rect, response) =
+                        ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+                    self.show_image(ui, &response, rect, &display, key, pair.rotation);
+                } else if self.image_cache.is_failed(key) {
                     ui.centered_and_justified(|ui| {
-                        ui.image((texture.id(), display_size));
+                        ui.label("Could not decode this image");
                     });
                 } else {
                     // Image still loading - show loading indicator and request repaint
@@ -272,38 +2064,56 @@ impl eframe::App for PhotoCullerApp {
 
                     ui.separator();
 
+                    for &mode in DeletionMode::available() {
+                        ui.radio_value(&mut self.deletion_mode, mode, mode.label());
+                    }
+
+                    ui.separator();
+
                     ui.horizontal(|ui| {
                         if ui.button("Cancel").clicked() {
                             self.show_delete_dialog = false;
                         }
-                        if summary.total_files() > 0 {
-                            if ui.button("Delete").clicked() {
-                                match deletion::execute_deletions(&self.pairs) {
-                                    Ok(count) => {
-                                        self.status_message =
-                                            Some(format!("Deleted {} files", count));
-                                        // Rescan the directory and clear cache (files changed)
-                                        self.image_cache.clear();
-                                        if let Some(ref path) = self.folder_path {
-                                            if let Ok(pairs) = scanner::scan_directory(path) {
-                                                self.pairs = pairs;
-                                                self.current_index =
-                                                    self.current_index.min(self.pairs.len().saturating_sub(1));
-                                            }
-                                        }
-                                    }
-                                    Err(errors) => {
-                                        self.status_message = Some(format!(
-                                            "Errors during deletion: {}",
-                                            errors.join(", ")
-                                        ));
-                                    }
-                                }
-                                self.show_delete_dialog = false;
-                            }
+                        if summary.total_files() > 0
+                            && ui.button(self.deletion_mode.label()).clicked()
+                        {
+                            self.delete_marked();
+                            self.show_delete_dialog = false;
                         }
                     });
                 });
         }
+
+        // Reject duplicates confirmation dialog
+        if self.show_duplicates && self.show_reject_duplicates_dialog {
+            egui::Window::new("Reject Duplicates")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    let groups = self.duplicate_groups.len();
+                    let extras: usize =
+                        self.duplicate_groups.iter().map(|g| g.members.len() - 1).sum();
+                    ui.label(format!(
+                        "Mark {} photos in {} groups as Delete Both?",
+                        extras, groups
+                    ));
+                    ui.label("Each group keeps its highlighted photo.");
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Cancel").clicked() {
+                            self.show_reject_duplicates_dialog = false;
+                        }
+                        if ui.button("Reject Extras").clicked() {
+                            self.reject_duplicates(&(0..groups).collect::<Vec<_>>());
+                            self.show_reject_duplicates_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        self.autosave_session(ctx);
     }
 }
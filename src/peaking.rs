@@ -0,0 +1,57 @@
+//! Focus peaking: marks the strongest edges of a photo so the plane of focus
+//! shows without zooming in.
+
+use crate::analysis;
+use crate::viewer::DecodedImage;
+
+/// Edge strength from which an edge is highlighted by default.
+pub const DEFAULT_THRESHOLD: u8 = 50;
+
+/// Edge strength of a full-resolution image, reduced to at most `max_size`
+/// on the longest side. Each reduced pixel keeps the strongest edge it
+/// covers, so fine detail that is in focus isn't averaged away.
+///
+/// Strength is the Sobel gradient of the luma, scaled so a hard step between
+/// two levels scores about their difference. The map is returned as white
+/// pixels with the strength as alpha, to be cached like any other decoded
+/// image.
+pub fn edge_map(image: &DecodedImage, max_size: usize) -> DecodedImage {
+    let (width, height) = (image.width, image.height);
+    let luma: Vec<u8> = image
+        .pixels
+        .chunks_exact(4)
+        .map(|pixel| analysis::luma(pixel).round() as u8)
+        .collect();
+
+    let factor = width.max(height).div_ceil(max_size.max(1)).max(1);
+    let (out_width, out_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut strength = vec![0u8; out_width * out_height];
+    for y in 1..height.saturating_sub(1) {
+        let above = &luma[(y - 1) * width..y * width];
+        let row = &luma[y * width..(y + 1) * width];
+        let below = &luma[(y + 1) * width..(y + 2) * width];
+        let out_row = &mut strength[(y / factor) * out_width..(y / factor + 1) * out_width];
+        for x in 1..width.saturating_sub(1) {
+            let at = |line: &[u8], x: usize| line[x] as i32;
+            let gx = at(above, x + 1) + 2 * at(row, x + 1) + at(below, x + 1)
+                - at(above, x - 1)
+                - 2 * at(row, x - 1)
+                - at(below, x - 1);
+            let gy = at(below, x - 1) + 2 * at(below, x) + at(below, x + 1)
+                - at(above, x - 1)
+                - 2 * at(above, x)
+                - at(above, x + 1);
+            let magnitude = (((gx * gx + gy * gy) as f32).sqrt() / 4.0).min(255.0) as u8;
+            let cell = &mut out_row[x / factor];
+            *cell = (*cell).max(magnitude);
+        }
+    }
+
+    DecodedImage {
+        pixels: strength.iter().flat_map(|&s| [255, 255, 255, s]).collect(),
+        width: out_width,
+        height: out_height,
+        source_size: [width, height],
+        stats: None,
+    }
+}
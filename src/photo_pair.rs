@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+use serde::{Deserialize, Serialize};
+
+use crate::exif::ExifData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum DeletionAction {
     #[default]
     KeepBoth,
@@ -18,32 +22,148 @@ impl DeletionAction {
             DeletionAction::DeleteBoth => "Delete Both",
         }
     }
+
+    pub fn deletes_jpeg(&self) -> bool {
+        matches!(self, DeletionAction::DeleteJpeg | DeletionAction::DeleteBoth)
+    }
+
+    pub fn deletes_raw(&self) -> bool {
+        matches!(self, DeletionAction::DeleteRaw | DeletionAction::DeleteBoth)
+    }
+}
+
+/// Colour label, using the same five colours as Lightroom and darktable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub const ALL: [ColorLabel; 5] = [
+        ColorLabel::Red,
+        ColorLabel::Yellow,
+        ColorLabel::Green,
+        ColorLabel::Blue,
+        ColorLabel::Purple,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorLabel::Red => "Red",
+            ColorLabel::Yellow => "Yellow",
+            ColorLabel::Green => "Green",
+            ColorLabel::Blue => "Blue",
+            ColorLabel::Purple => "Purple",
+        }
+    }
 }
 
+/// Highest star rating a photo can be given.
+pub const MAX_RATING: u8 = 5;
+
+/// A JPEG and/or RAW file sharing the same basename in the same directory.
+/// At least one of the two paths is always present.
 #[derive(Debug, Clone)]
 pub struct PhotoPair {
     pub basename: String,
-    pub jpeg_path: PathBuf,
+    /// Directory of the files relative to the scanned folder; empty for the
+    /// folder itself.
+    pub relative_dir: PathBuf,
+    pub jpeg_path: Option<PathBuf>,
     pub raw_path: Option<PathBuf>,
     pub action: DeletionAction,
+    /// Star rating from 0 (unrated) to `MAX_RATING`.
+    pub rating: u8,
+    pub color_label: Option<ColorLabel>,
+    /// Manual rotation in clockwise quarter turns (0-3), applied on top of
+    /// the EXIF orientation.
+    pub rotation: u8,
+    pub exif: Option<ExifData>,
+    /// Burst this pair was shot in, shared by every frame of the burst.
+    pub burst: Option<u32>,
 }
 
 impl PhotoPair {
-    pub fn new(basename: String, jpeg_path: PathBuf, raw_path: Option<PathBuf>) -> Self {
-        let action = if raw_path.is_some() {
-            DeletionAction::KeepBoth
-        } else {
-            DeletionAction::DeleteRaw
-        };
-        Self {
+    pub fn new(
+        relative_dir: PathBuf,
+        basename: String,
+        jpeg_path: Option<PathBuf>,
+        raw_path: Option<PathBuf>,
+    ) -> Self {
+        debug_assert!(jpeg_path.is_some() || raw_path.is_some());
+        let mut pair = Self {
             basename,
+            relative_dir,
             jpeg_path,
             raw_path,
-            action,
+            action: DeletionAction::KeepBoth,
+            rating: 0,
+            color_label: None,
+            rotation: 0,
+            exif: None,
+            burst: None,
+        };
+        pair.action = pair.default_action();
+        pair
+    }
+
+    /// Identifies the pair within a scan: the uppercase stem, prefixed with
+    /// its relative directory when it lives in a subfolder.
+    pub fn key(&self) -> String {
+        if self.relative_dir.as_os_str().is_empty() {
+            self.basename.clone()
+        } else {
+            let dir = self.relative_dir.to_string_lossy().replace('\\', "/");
+            format!("{}/{}", dir, self.basename)
+        }
+    }
+
+    /// The displayed file's path relative to the scanned folder.
+    pub fn relative_display_path(&self) -> PathBuf {
+        self.relative_dir
+            .join(self.display_path().file_name().unwrap_or_default())
+    }
+
+    /// Rotates by `quarter_turns` clockwise; negative values turn counter-clockwise.
+    pub fn rotate(&mut self, quarter_turns: i8) {
+        self.rotation = (self.rotation as i8 + quarter_turns).rem_euclid(4) as u8;
+    }
+
+    /// The action a freshly scanned pair starts with.
+    pub fn default_action(&self) -> DeletionAction {
+        match (&self.jpeg_path, &self.raw_path) {
+            (Some(_), Some(_)) => DeletionAction::KeepBoth,
+            (Some(_), None) => DeletionAction::DeleteRaw,
+            (None, _) => DeletionAction::DeleteJpeg,
         }
     }
 
     pub fn has_raw(&self) -> bool {
         self.raw_path.is_some()
     }
+
+    pub fn has_jpeg(&self) -> bool {
+        self.jpeg_path.is_some()
+    }
+
+    /// The file shown in the viewer: the camera JPEG when there is one,
+    /// otherwise the RAW (rendered from its embedded preview).
+    pub fn display_path(&self) -> &Path {
+        self.jpeg_path
+            .as_deref()
+            .or(self.raw_path.as_deref())
+            .expect("PhotoPair without JPEG or RAW")
+    }
+
+    /// Files that the current action would remove, skipping sides that
+    /// don't exist on disk.
+    pub fn marked_files(&self) -> (Option<&Path>, Option<&Path>) {
+        let jpeg = self.jpeg_path.as_deref().filter(|_| self.action.deletes_jpeg());
+        let raw = self.raw_path.as_deref().filter(|_| self.action.deletes_raw());
+        (jpeg, raw)
+    }
 }
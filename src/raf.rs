@@ -0,0 +1,368 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::exif;
+use crate::tiff::Tiff;
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+/// Size of the fixed header holding the section directory.
+const HEADER_LEN: usize = 108;
+const FORMAT_VERSION_OFFSET: usize = 16;
+const CAMERA_MODEL_OFFSET: usize = 28;
+const CAMERA_MODEL_LEN: usize = 32;
+/// Start of the big-endian (offset, length) pairs locating each section,
+/// beginning with the JPEG preview.
+const SECTIONS_OFFSET: usize = 84;
+/// Enough of the preview to cover its APP1 EXIF block and frame header.
+const PREVIEW_HEAD_BYTES: usize = 256 * 1024;
+
+// Records in the CFA header section
+const RECORD_CROP_TOP_LEFT: u16 = 0x0110;
+const RECORD_CROPPED_SIZE: u16 = 0x0111;
+const RECORD_XTRANS_LAYOUT: u16 = 0x0131;
+const RECORD_WB_GRGB_LEVELS: u16 = 0x2FF0;
+
+// Tags of the Fuji raw IFD at the start of the CFA section
+const TAG_RAW_IFD: u16 = 0xF000;
+const TAG_RAW_WIDTH: u16 = 0xF001;
+const TAG_RAW_HEIGHT: u16 = 0xF002;
+const TAG_BITS_PER_SAMPLE: u16 = 0xF003;
+const TAG_STRIP_OFFSET: u16 = 0xF007;
+const TAG_STRIP_BYTE_COUNT: u16 = 0xF008;
+const TAG_BLACK_LEVEL: u16 = 0xF00A;
+const TAG_WB_GRB_LEVELS: u16 = 0xF00E;
+
+/// Colour filter layout of the sensor. Values are 0 = red, 1 = green,
+/// 2 = blue, indexed by absolute sensor row and column.
+#[derive(Debug, Clone, Copy)]
+pub enum CfaPattern {
+    XTrans([[u8; 6]; 6]),
+    Bayer([[u8; 2]; 2]),
+}
+
+impl CfaPattern {
+    pub fn color_at(&self, row: usize, col: usize) -> u8 {
+        match self {
+            CfaPattern::XTrans(layout) => layout[row % 6][col % 6],
+            CfaPattern::Bayer(layout) => layout[row % 2][col % 2],
+        }
+    }
+}
+
+/// Unpacked sensor data with what is needed to develop it.
+pub struct CfaImage {
+    pub width: usize,
+    pub height: usize,
+    /// One sample per photosite, row-major over the full sensor.
+    pub samples: Vec<u16>,
+    /// Visible area as (top, left, height, width).
+    pub crop: (usize, usize, usize, usize),
+    pub pattern: CfaPattern,
+    pub black_level: f32,
+    pub white_level: f32,
+    /// As-shot white balance multipliers for red, green and blue.
+    pub wb_multipliers: [f32; 3],
+}
+
+/// Location of one block of data inside the RAF file.
+#[derive(Debug, Clone, Copy)]
+pub struct Section {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Dimensions and orientation of the embedded JPEG preview.
+#[derive(Debug, Clone, Copy)]
+pub struct PreviewInfo {
+    pub width: u32,
+    pub height: u32,
+    /// EXIF orientation, 1 if the preview has none.
+    pub orientation: u16,
+}
+
+/// A Fujifilm RAF container. Only the header is read up front; sections are
+/// loaded on demand.
+#[derive(Debug, Clone)]
+pub struct RafFile {
+    path: PathBuf,
+    pub format_version: String,
+    pub camera_model: String,
+    /// Full-size camera-rendered JPEG.
+    pub preview: Section,
+    /// Records describing the sensor layout, crop and white balance.
+    pub cfa_header: Section,
+    /// Raw sensor data.
+    pub cfa: Section,
+}
+
+impl RafFile {
+    pub fn open(path: &Path) -> Option<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        File::open(path).ok()?.read_exact(&mut header).ok()?;
+        if !header.starts_with(RAF_MAGIC) {
+            return None;
+        }
+
+        let section = |index: usize| {
+            let pos = SECTIONS_OFFSET + index * 8;
+            Some(Section {
+                offset: read_u32_be(&header, pos)? as u64,
+                length: read_u32_be(&header, pos + 4)? as u64,
+            })
+        };
+
+        Some(Self {
+            path: path.to_path_buf(),
+            format_version: ascii(&header[FORMAT_VERSION_OFFSET..FORMAT_VERSION_OFFSET + 4]),
+            camera_model: ascii(
+                &header[CAMERA_MODEL_OFFSET..CAMERA_MODEL_OFFSET + CAMERA_MODEL_LEN],
+            ),
+            preview: section(0)?,
+            cfa_header: section(1)?,
+            cfa: section(2)?,
+        })
+    }
+
+    /// Reads a whole section, or its first `limit` bytes.
+    pub fn read_section(&self, section: Section, limit: usize) -> Option<Vec<u8>> {
+        if section.length == 0 {
+            return None;
+        }
+        let mut file = File::open(&self.path).ok()?;
+        let mut data = vec![0u8; (section.length as usize).min(limit)];
+        file.seek(SeekFrom::Start(section.offset)).ok()?;
+        file.read_exact(&mut data).ok()?;
+        Some(data)
+    }
+
+    /// The full-size JPEG preview embedded by the camera.
+    pub fn read_preview(&self) -> Option<Vec<u8>> {
+        self.read_section(self.preview, usize::MAX)
+    }
+
+    /// The start of the preview, enough to get at its EXIF block without
+    /// loading the whole image.
+    pub fn read_preview_head(&self) -> Option<Vec<u8>> {
+        self.read_section(self.preview, PREVIEW_HEAD_BYTES)
+    }
+
+    pub fn preview_info(&self) -> Option<PreviewInfo> {
+        let head = self.read_preview_head()?;
+        let (width, height) = jpeg_dimensions(&head)?;
+        let orientation = exif::parse_jpeg(&head)
+            .and_then(|e| e.orientation)
+            .unwrap_or(1);
+        Some(PreviewInfo {
+            width,
+            height,
+            orientation,
+        })
+    }
+
+    /// Loads and unpacks the raw sensor data. Only uncompressed RAFs are
+    /// supported; lossless and lossy compressed files return `None`.
+    pub fn read_cfa(&self) -> Option<CfaImage> {
+        let records = self.read_section(self.cfa_header, usize::MAX)?;
+        let records = parse_records(&records);
+        let record = |tag| records.iter().find(|(t, _)| *t == tag).map(|(_, d)| d.as_slice());
+        let u16_pair = |tag| {
+            let data = record(tag)?;
+            Some((
+                read_u16_be(data, 0)? as usize,
+                read_u16_be(data, 2)? as usize,
+            ))
+        };
+
+        let data = self.read_section(self.cfa, usize::MAX)?;
+        let tiff = Tiff::parse(&data)?;
+        let root = tiff.ifd(tiff.first_ifd())?;
+        let raw_ifd = tiff.ifd(tiff.uint(root.get(TAG_RAW_IFD)?)?)?;
+        let uint = |tag| raw_ifd.get(tag).and_then(|e| tiff.uint(e));
+
+        let width = uint(TAG_RAW_WIDTH)? as usize;
+        let height = uint(TAG_RAW_HEIGHT)? as usize;
+        let bits = uint(TAG_BITS_PER_SAMPLE).unwrap_or(14);
+        let strip_offset = uint(TAG_STRIP_OFFSET)? as usize;
+        let strip_length = uint(TAG_STRIP_BYTE_COUNT)? as usize;
+        let strip = data.get(strip_offset..strip_offset.checked_add(strip_length)?)?;
+
+        let count = width * height;
+        let stored_bits = if strip_length * 8 >= count * 16 {
+            16
+        } else if strip_length * 8 >= count * 14 {
+            14
+        } else if strip_length * 8 >= count * 12 {
+            12
+        } else {
+            eprintln!("{}: compressed RAF data is not supported", self.path.display());
+            return None;
+        };
+        let samples = unpack_samples(strip, stored_bits, count, tiff.is_big_endian());
+
+        let black_level = raw_ifd
+            .get(TAG_BLACK_LEVEL)
+            .and_then(|e| tiff.uints(e))
+            .filter(|levels| !levels.is_empty())
+            .map(|levels| levels.iter().sum::<u32>() as f32 / levels.len() as f32)
+            .unwrap_or(0.0);
+
+        // The record is G, R, G, B; the IFD tag is G, R, B
+        let wb_levels = record(RECORD_WB_GRGB_LEVELS)
+            .and_then(|d| Some([read_u16_be(d, 2)?, read_u16_be(d, 0)?, read_u16_be(d, 6)?]))
+            .map(|[r, g, b]| [r as u32, g as u32, b as u32])
+            .or_else(|| {
+                let grb = raw_ifd.get(TAG_WB_GRB_LEVELS).and_then(|e| tiff.uints(e))?;
+                Some([*grb.get(1)?, *grb.first()?, *grb.get(2)?])
+            })
+            .filter(|levels| levels[1] > 0);
+        let wb_multipliers = match wb_levels {
+            Some([r, g, b]) => [r as f32 / g as f32, 1.0, b as f32 / g as f32],
+            None => [1.0, 1.0, 1.0],
+        };
+
+        // X-Trans layouts are stored in reverse order
+        let pattern = match record(RECORD_XTRANS_LAYOUT) {
+            Some(layout) if layout.len() >= 36 => {
+                let mut xtrans = [[0u8; 6]; 6];
+                for (i, &color) in layout[..36].iter().enumerate() {
+                    let pos = 35 - i;
+                    xtrans[pos / 6][pos % 6] = color.min(2);
+                }
+                CfaPattern::XTrans(xtrans)
+            }
+            _ => CfaPattern::Bayer([[0, 1], [1, 2]]),
+        };
+
+        let (top, left) = u16_pair(RECORD_CROP_TOP_LEFT).unwrap_or((0, 0));
+        let (crop_height, crop_width) = u16_pair(RECORD_CROPPED_SIZE)
+            .unwrap_or_else(|| (height.saturating_sub(top), width.saturating_sub(left)));
+        let crop = (
+            top.min(height),
+            left.min(width),
+            crop_height.min(height.saturating_sub(top)),
+            crop_width.min(width.saturating_sub(left)),
+        );
+
+        Some(CfaImage {
+            width,
+            height,
+            samples,
+            crop,
+            pattern,
+            black_level,
+            white_level: ((1u32 << bits.min(16)) - 1) as f32,
+            wb_multipliers,
+        })
+    }
+}
+
+/// Splits the CFA header section into (tag, data) records.
+fn parse_records(data: &[u8]) -> Vec<(u16, Vec<u8>)> {
+    let mut records = Vec::new();
+    let count = read_u32_be(data, 0).unwrap_or(0);
+    let mut pos = 4;
+    for _ in 0..count {
+        let (Some(tag), Some(size)) = (read_u16_be(data, pos), read_u16_be(data, pos + 2)) else {
+            break;
+        };
+        let start = pos + 4;
+        let Some(record) = data.get(start..start + size as usize) else {
+            break;
+        };
+        records.push((tag, record.to_vec()));
+        pos = start + size as usize;
+    }
+    records
+}
+
+/// Unpacks `count` samples stored at `bits` per sample. 16-bit samples are
+/// plain words; packed ones are read most-significant bit first in
+/// big-endian files and least-significant first otherwise.
+fn unpack_samples(data: &[u8], bits: u32, count: usize, big_endian: bool) -> Vec<u16> {
+    if bits == 16 {
+        return data
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| {
+                if big_endian {
+                    u16::from_be_bytes([b[0], b[1]])
+                } else {
+                    u16::from_le_bytes([b[0], b[1]])
+                }
+            })
+            .collect();
+    }
+
+    let mask = (1u64 << bits) - 1;
+    let mut samples = Vec::with_capacity(count);
+    let mut buffer = 0u64;
+    let mut buffered = 0u32;
+    for &byte in data {
+        if big_endian {
+            buffer = (buffer << 8) | byte as u64;
+        } else {
+            buffer |= (byte as u64) << buffered;
+        }
+        buffered += 8;
+        while buffered >= bits {
+            let sample = if big_endian {
+                (buffer >> (buffered - bits)) & mask
+            } else {
+                let sample = buffer & mask;
+                buffer >>= bits;
+                sample
+            };
+            buffered -= bits;
+            samples.push(sample as u16);
+            if samples.len() == count {
+                return samples;
+            }
+        }
+        if big_endian {
+            buffer &= (1u64 << buffered) - 1;
+        }
+    }
+    samples
+}
+
+/// Width and height from a JPEG's start-of-frame header.
+pub fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 9 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let height = u16::from_be_bytes([data[pos + 5], data[pos + 6]]);
+            let width = u16::from_be_bytes([data[pos + 7], data[pos + 8]]);
+            return Some((width as u32, height as u32));
+        }
+        if marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 2 + length;
+    }
+    None
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn read_u16_be(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
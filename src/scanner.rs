@@ -2,40 +2,84 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
+use crate::burst;
+use crate::deletion::CULLED_DIR_NAME;
+use crate::exif::{self, ExifData};
 use crate::photo_pair::PhotoPair;
+use crate::session::Session;
+use crate::xmp;
 
 const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
-const RAW_EXTENSION: &str = "raf";
 
-pub fn scan_directory(dir: &Path) -> Result<Vec<PhotoPair>, std::io::Error> {
-    println!("Scanning directory: {}", dir.display());
+/// RAW extensions the scanner knows about. When a folder holds several RAW
+/// files with the same basename, the one listed first wins, which keeps
+/// camera-native formats ahead of converted DNGs.
+pub const KNOWN_RAW_EXTENSIONS: &[&str] = &[
+    "raf", "arw", "sr2", "srf", "cr2", "cr3", "crw", "nef", "nrw", "orf", "rw2", "rwl", "pef",
+    "srw", "3fr", "iiq", "x3f", "erf", "mrw", "dng",
+];
+
+struct ScannedFile {
+    /// Lowercase extension.
+    ext: String,
+    /// Uppercase file stem.
+    base: String,
+    relative_dir: PathBuf,
+    path: PathBuf,
+}
 
-    let entries = std::fs::read_dir(dir)?;
+pub fn is_raw_extension(ext: &str) -> bool {
+    KNOWN_RAW_EXTENSIONS.contains(&ext)
+}
 
-    let mut jpegs: HashMap<String, PathBuf> = HashMap::new();
-    let mut raws: HashMap<String, PathBuf> = HashMap::new();
+/// RAW extensions present in `dir`, in `KNOWN_RAW_EXTENSIONS` order. Used as
+/// the folder's default when no explicit set has been configured.
+pub fn detect_raw_extensions(dir: &Path, recursive: bool) -> Result<Vec<String>, std::io::Error> {
+    Ok(raw_extensions_in(&list_files(dir, recursive)?))
+}
 
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
-        }
+fn raw_extensions_in(files: &[ScannedFile]) -> Vec<String> {
+    KNOWN_RAW_EXTENSIONS
+        .iter()
+        .filter(|ext| files.iter().any(|f| f.ext == **ext))
+        .map(|ext| ext.to_string())
+        .collect()
+}
+
+/// EXIF read so far, by file. Kept across rescans of a folder so that
+/// deleting, undoing or switching RAW formats doesn't read every file again.
+pub type ExifCache = HashMap<PathBuf, Option<ExifData>>;
+
+pub fn scan_directory(
+    dir: &Path,
+    exif_cache: &mut ExifCache,
+) -> Result<Vec<PhotoPair>, std::io::Error> {
+    println!("Scanning directory: {}", dir.display());
+
+    let session = Session::load(dir);
+    let files = list_files(dir, session.scan.recursive)?;
+    let raw_extensions = match session.scan.raw_extensions {
+        Some(ref extensions) => extensions.clone(),
+        None => raw_extensions_in(&files),
+    };
+    println!("RAW extensions: {}", raw_extensions.join(", "));
+
+    // Files only pair up within the same directory, so DSCF0001 in 100_FUJI
+    // and 101_FUJI stay separate photos
+    let mut jpegs: HashMap<(PathBuf, String), PathBuf> = HashMap::new();
+    let mut raws: HashMap<(PathBuf, String), (usize, PathBuf)> = HashMap::new();
 
-        let extension = path
-            .extension()
-            .and_then(OsStr::to_str)
-            .map(|s| s.to_lowercase());
-
-        let basename = path
-            .file_stem()
-            .and_then(OsStr::to_str)
-            .map(|s| s.to_uppercase());
-
-        if let (Some(ext), Some(base)) = (extension, basename) {
-            if JPEG_EXTENSIONS.contains(&ext.as_str()) {
-                jpegs.insert(base, path);
-            } else if ext == RAW_EXTENSION {
-                raws.insert(base, path);
+    for file in files {
+        let key = (file.relative_dir, file.base);
+        if JPEG_EXTENSIONS.contains(&file.ext.as_str()) {
+            jpegs.insert(key, file.path);
+        } else if let Some(rank) = raw_extensions.iter().position(|r| *r == file.ext) {
+            // Keep the preferred format when one shot exists in several RAW formats
+            match raws.get(&key) {
+                Some((existing, _)) if *existing <= rank => {}
+                _ => {
+                    raws.insert(key, (rank, file.path));
+                }
             }
         }
     }
@@ -44,15 +88,185 @@ pub fn scan_directory(dir: &Path) -> Result<Vec<PhotoPair>, std::io::Error> {
 
     let mut pairs: Vec<PhotoPair> = jpegs
         .into_iter()
-        .map(|(basename, jpeg_path)| {
-            let raw_path = raws.remove(&basename);
-            PhotoPair::new(basename, jpeg_path, raw_path)
+        .map(|(key, jpeg_path)| {
+            let raw_path = raws.remove(&key).map(|(_, path)| path);
+            let (relative_dir, basename) = key;
+            PhotoPair::new(relative_dir, basename, Some(jpeg_path), raw_path)
         })
         .collect();
 
-    pairs.sort_by(|a, b| a.basename.cmp(&b.basename));
+    // Whatever is left in `raws` has no JPEG partner
+    pairs.extend(raws.into_iter().map(|((relative_dir, basename), (_, raw_path))| {
+        PhotoPair::new(relative_dir, basename, None, Some(raw_path))
+    }));
+
+    pairs.sort_by(|a, b| {
+        a.relative_dir
+            .cmp(&b.relative_dir)
+            .then_with(|| a.basename.cmp(&b.basename))
+    });
+
+    read_exif(&mut pairs, exif_cache);
+    burst::group_bursts(&mut pairs);
+
+    // The session first, then sidecars on top so edits made in other
+    // tools since the last visit win
+    session.apply(&mut pairs);
+    for pair in &mut pairs {
+        xmp::apply_sidecar(pair);
+    }
 
     println!("Scan complete: {} pairs", pairs.len());
 
     Ok(pairs)
 }
+
+/// Attaches EXIF data to every pair, preferring the camera JPEG over the
+/// RAW. Only files missing from `cache` are read.
+fn read_exif(pairs: &mut [PhotoPair], cache: &mut ExifCache) {
+    let jpegs: Vec<PathBuf> = pairs.iter().filter_map(|p| p.jpeg_path.clone()).collect();
+    read_uncached(jpegs, cache);
+    // RAWs are only read for pairs whose JPEG is missing or had no EXIF
+    let raws: Vec<PathBuf> = pairs
+        .iter()
+        .filter(|p| {
+            p.jpeg_path
+                .as_ref()
+                .and_then(|j| cache.get(j)?.as_ref())
+                .is_none()
+        })
+        .filter_map(|p| p.raw_path.clone())
+        .collect();
+    read_uncached(raws, cache);
+
+    for pair in pairs {
+        pair.exif = [&pair.jpeg_path, &pair.raw_path]
+            .into_iter()
+            .flatten()
+            .find_map(|path| cache.get(path)?.clone());
+    }
+}
+
+/// Reads the EXIF of every path not in `cache` yet. Each file only needs its
+/// first few kilobytes read, so the work is split across threads to hide I/O
+/// latency.
+fn read_uncached(paths: Vec<PathBuf>, cache: &mut ExifCache) {
+    let paths: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|p| !cache.contains_key(p))
+        .collect();
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk_size = paths.len().div_ceil(threads).max(1);
+    let read: Vec<Option<ExifData>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(|p| exif::read_file(p)).collect::<Vec<_>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    cache.extend(paths.into_iter().zip(read));
+}
+
+/// Files in `dir`, and in its subfolders when `recursive` is set. Hidden
+/// folders and `_culled` staging folders are never descended into.
+fn list_files(dir: &Path, recursive: bool) -> Result<Vec<ScannedFile>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative_dir) = pending.pop() {
+        let entries = match std::fs::read_dir(dir.join(&relative_dir)) {
+            Ok(entries) => entries,
+            // Only the top-level folder is required to be readable
+            Err(e) if relative_dir.as_os_str().is_empty() => return Err(e),
+            Err(e) => {
+                eprintln!("Skipping {}: {}", relative_dir.display(), e);
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Don't follow symlinked directories, they can form cycles
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if recursive && !name.starts_with('.') && name != CULLED_DIR_NAME {
+                    pending.push(relative_dir.join(&*name));
+                }
+                continue;
+            }
+            if !path.is_file() {
+                continue;
+            }
+
+            let extension = path
+                .extension()
+                .and_then(OsStr::to_str)
+                .map(|s| s.to_lowercase());
+
+            let basename = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .map(|s| s.to_uppercase());
+
+            if let (Some(ext), Some(base)) = (extension, basename) {
+                files.push(ScannedFile {
+                    ext,
+                    base,
+                    relative_dir: relative_dir.clone(),
+                    path,
+                });
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::photo_pair::DeletionAction;
+    use crate::session::ScanSettings;
+
+    #[test]
+    fn sidecar_edits_after_the_session_survive_a_rescan() {
+        let dir = std::env::temp_dir().join(format!("photoculler-scan-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("DSCF0001.JPG"), b"").unwrap();
+
+        let mut pairs = scan_directory(&dir, &mut ExifCache::new()).unwrap();
+        pairs[0].rating = 2;
+        pairs[0].action = DeletionAction::DeleteBoth;
+        Session::from_pairs(&pairs, 0, ScanSettings::default())
+            .save(&dir)
+            .unwrap();
+
+        // Rated and un-rejected in another tool later on
+        fs::write(
+            dir.join("DSCF0001.xmp"),
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""
+      xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+      xmp:Rating="4"/>
+  </rdf:RDF>
+</x:xmpmeta>
+"#,
+        )
+        .unwrap();
+
+        let pairs = scan_directory(&dir, &mut ExifCache::new()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pairs[0].rating, 4);
+        assert_eq!(pairs[0].action, pairs[0].default_action());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::photo_pair::{ColorLabel, DeletionAction, MAX_RATING, PhotoPair};
+
+/// Session file kept in the opened folder.
+pub const SESSION_FILE_NAME: &str = ".photoculler.json";
+
+/// How a folder is scanned for photo pairs.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ScanSettings {
+    /// RAW extensions to pair with JPEGs, in order of preference. `None`
+    /// means auto-detect from the files in the folder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_extensions: Option<Vec<String>>,
+    /// Also scan subfolders, e.g. the numbered folders of a card dump.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub recursive: bool,
+}
+
+/// Everything the user decided about one photo. Fields left at their
+/// defaults are omitted from the file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhotoState {
+    /// Only stored when it differs from the pair's default action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<DeletionAction>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rating: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_label: Option<ColorLabel>,
+    /// Manual rotation in clockwise quarter turns.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: u8,
+}
+
+impl PhotoState {
+    fn from_pair(pair: &PhotoPair) -> Self {
+        Self {
+            action: Some(pair.action).filter(|&a| a != pair.default_action()),
+            rating: pair.rating,
+            color_label: pair.color_label,
+            rotation: pair.rotation,
+        }
+    }
+
+    fn apply(&self, pair: &mut PhotoPair) {
+        if let Some(action) = self.action {
+            pair.action = action;
+        }
+        pair.rating = self.rating.min(MAX_RATING);
+        pair.color_label = self.color_label;
+        pair.rotation = self.rotation % 4;
+    }
+}
+
+fn is_zero(value: &u8) -> bool {
+    *value == 0
+}
+
+/// A `PhotoState` as stored. Sessions written before ratings and labels
+/// existed hold a bare action instead.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPhotoState {
+    State(PhotoState),
+    Action(DeletionAction),
+}
+
+fn deserialize_photos<'de, D>(deserializer: D) -> Result<BTreeMap<String, PhotoState>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let stored = BTreeMap::<String, StoredPhotoState>::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .map(|(key, state)| {
+            let state = match state {
+                StoredPhotoState::State(state) => state,
+                StoredPhotoState::Action(action) => PhotoState {
+                    action: Some(action),
+                    ..PhotoState::default()
+                },
+            };
+            (key, state)
+        })
+        .collect())
+}
+
+/// Culling state of a folder that survives closing the app.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    /// Key of the photo that was on screen.
+    #[serde(default)]
+    pub current: Option<String>,
+    /// Per-photo state keyed by `PhotoPair::key`, for photos that have any.
+    /// Older sessions call this `decisions`.
+    #[serde(default, alias = "decisions", deserialize_with = "deserialize_photos")]
+    pub photos: BTreeMap<String, PhotoState>,
+    #[serde(flatten)]
+    pub scan: ScanSettings,
+}
+
+impl Session {
+    pub fn from_pairs(pairs: &[PhotoPair], current_index: usize, scan: ScanSettings) -> Self {
+        let photos = pairs
+            .iter()
+            .map(|p| (p.key(), PhotoState::from_pair(p)))
+            .filter(|(_, state)| *state != PhotoState::default())
+            .collect();
+        Self {
+            current: pairs.get(current_index).map(|p| p.key()),
+            photos,
+            scan,
+        }
+    }
+
+    /// Loads the session of `dir`, falling back to an empty one if the file
+    /// is missing or unreadable.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(SESSION_FILE_NAME);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Failed to read {}: {}", path.display(), e);
+                }
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring invalid session file {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        // Write then rename so a crash never leaves a truncated session behind
+        let tmp_path = dir.join(format!("{}.tmp", SESSION_FILE_NAME));
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, dir.join(SESSION_FILE_NAME))
+    }
+
+    pub fn apply(&self, pairs: &mut [PhotoPair]) {
+        for pair in pairs {
+            if let Some(state) = self.photos.get(&pair.key()) {
+                state.apply(pair);
+            }
+        }
+    }
+
+    /// Index of the photo that was on screen when the session was saved.
+    pub fn current_index(&self, pairs: &[PhotoPair]) -> Option<usize> {
+        let current = self.current.as_ref()?;
+        pairs.iter().position(|p| &p.key() == current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_decisions_from_older_sessions() {
+        let json = r#"{"current": "DSCF0002", "decisions": {"DSCF0001": "DeleteRaw"}}"#;
+        let session: Session = serde_json::from_str(json).unwrap();
+        assert_eq!(session.current.as_deref(), Some("DSCF0002"));
+        assert_eq!(
+            session.photos["DSCF0001"],
+            PhotoState {
+                action: Some(DeletionAction::DeleteRaw),
+                ..PhotoState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn reads_photo_states() {
+        let json = r#"{"photos": {"DSCF0001": {"rating": 3, "color_label": "Red"}}}"#;
+        let session: Session = serde_json::from_str(json).unwrap();
+        let state = &session.photos["DSCF0001"];
+        assert_eq!(state.action, None);
+        assert_eq!(state.rating, 3);
+        assert_eq!(state.color_label, Some(ColorLabel::Red));
+    }
+}
@@ -0,0 +1,354 @@
+/// Minimal TIFF structure reader. Most RAW formats (ARW, CR2, NEF, DNG, PEF,
+/// ORF, RW2, ...) and EXIF blocks are TIFF files underneath.
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+    first_ifd: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IfdEntry {
+    pub tag: u16,
+    pub kind: u16,
+    pub count: u32,
+    /// Position of the 4-byte value/offset field within the data.
+    field_pos: usize,
+}
+
+pub struct Ifd {
+    pub entries: Vec<IfdEntry>,
+    pub next: u32,
+}
+
+impl Ifd {
+    pub fn get(&self, tag: u16) -> Option<&IfdEntry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+}
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// Guards against malformed files with cyclic or endless IFD chains.
+const MAX_IFDS: usize = 64;
+
+impl<'a> Tiff<'a> {
+    /// Parses the header. Any magic number is accepted since several RAW
+    /// formats replace the standard 42 with their own.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(0..2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        let mut tiff = Self {
+            data,
+            big_endian,
+            first_ifd: 0,
+        };
+        tiff.first_ifd = tiff.u32_at(4)?;
+        Some(tiff)
+    }
+
+    /// Reader over a headerless IFD block, such as a maker note, whose
+    /// offsets are relative to the start of `data`.
+    pub fn headerless(data: &'a [u8], big_endian: bool) -> Self {
+        Self {
+            data,
+            big_endian,
+            first_ifd: 0,
+        }
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn first_ifd(&self) -> u32 {
+        self.first_ifd
+    }
+
+    pub fn u16_at(&self, pos: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn u32_at(&self, pos: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn ifd(&self, offset: u32) -> Option<Ifd> {
+        let pos = offset as usize;
+        let count = self.u16_at(pos)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry_pos = pos + 2 + i * 12;
+            entries.push(IfdEntry {
+                tag: self.u16_at(entry_pos)?,
+                kind: self.u16_at(entry_pos + 2)?,
+                count: self.u32_at(entry_pos + 4)?,
+                field_pos: entry_pos + 8,
+            });
+        }
+        let next = self.u32_at(pos + 2 + count * 12).unwrap_or(0);
+        Some(Ifd { entries, next })
+    }
+
+    /// Position of an entry's value within the data, following the offset
+    /// when the value does not fit in the entry itself, and its size.
+    fn value_pos(&self, entry: &IfdEntry) -> Option<(usize, usize)> {
+        let size = type_size(entry.kind)?.checked_mul(entry.count as usize)?;
+        let pos = if size <= 4 {
+            entry.field_pos
+        } else {
+            self.u32_at(entry.field_pos)? as usize
+        };
+        if pos.checked_add(size)? > self.data.len() {
+            return None;
+        }
+        Some((pos, size))
+    }
+
+    /// Raw bytes of an entry's value.
+    pub fn value_bytes(&self, entry: &IfdEntry) -> Option<&'a [u8]> {
+        let (pos, size) = self.value_pos(entry)?;
+        self.data.get(pos..pos + size)
+    }
+
+    /// Integer values of a BYTE, SHORT or LONG entry.
+    pub fn uints(&self, entry: &IfdEntry) -> Option<Vec<u32>> {
+        let (pos, _) = self.value_pos(entry)?;
+        (0..entry.count as usize)
+            .map(|i| match entry.kind {
+                1 | 7 => self.data.get(pos + i).map(|&b| b as u32),
+                3 => self.u16_at(pos + i * 2).map(u32::from),
+                4 | 13 => self.u32_at(pos + i * 4),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn uint(&self, entry: &IfdEntry) -> Option<u32> {
+        self.uints(entry)?.first().copied()
+    }
+
+    /// First value of a RATIONAL or SRATIONAL entry.
+    pub fn rational(&self, entry: &IfdEntry) -> Option<(i64, i64)> {
+        let (pos, _) = self.value_pos(entry)?;
+        let num = self.u32_at(pos)?;
+        let den = self.u32_at(pos + 4)?;
+        match entry.kind {
+            5 => Some((num as i64, den as i64)),
+            10 => Some((num as i32 as i64, den as i32 as i64)),
+            _ => None,
+        }
+    }
+
+    pub fn ascii(&self, entry: &IfdEntry) -> Option<String> {
+        let bytes = self.value_bytes(entry)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+        Some(text).filter(|t| !t.is_empty())
+    }
+
+    /// Every IFD reachable from the header: the main chain plus SubIFDs.
+    pub fn all_ifds(&self) -> Vec<Ifd> {
+        let mut pending = vec![self.first_ifd];
+        let mut visited = Vec::new();
+        let mut ifds = Vec::new();
+
+        while let Some(offset) = pending.pop() {
+            if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_IFDS {
+                continue;
+            }
+            visited.push(offset);
+            let Some(ifd) = self.ifd(offset) else {
+                continue;
+            };
+            pending.push(ifd.next);
+            if let Some(sub_ifds) = ifd.get(TAG_SUB_IFDS).and_then(|e| self.uints(e)) {
+                pending.extend(sub_ifds);
+            }
+            ifds.push(ifd);
+        }
+        ifds
+    }
+
+    /// The largest JPEG preview stored in any IFD, as used by most RAW
+    /// formats for their camera-rendered preview.
+    pub fn largest_embedded_jpeg(&self) -> Option<&'a [u8]> {
+        let mut best: Option<&'a [u8]> = None;
+
+        for ifd in self.all_ifds() {
+            let (offset, length) = if let (Some(offset), Some(length)) =
+                (ifd.get(TAG_JPEG_OFFSET), ifd.get(TAG_JPEG_LENGTH))
+            {
+                (self.uint(offset), self.uint(length))
+            } else {
+                // DNG stores its preview as a single JPEG-compressed strip in a
+                // reduced-resolution IFD
+                let compression = ifd.get(TAG_COMPRESSION).and_then(|e| self.uint(e));
+                let subfile_type = ifd.get(TAG_NEW_SUBFILE_TYPE).and_then(|e| self.uint(e));
+                if !matches!(compression, Some(6 | 7)) || subfile_type != Some(1) {
+                    continue;
+                }
+                let offsets = ifd.get(TAG_STRIP_OFFSETS).and_then(|e| self.uints(e));
+                let lengths = ifd.get(TAG_STRIP_BYTE_COUNTS).and_then(|e| self.uints(e));
+                match (offsets.as_deref(), lengths.as_deref()) {
+                    (Some([offset]), Some([length])) => (Some(*offset), Some(*length)),
+                    _ => continue,
+                }
+            };
+
+            let (Some(offset), Some(length)) = (offset, length) else {
+                continue;
+            };
+            let Some(jpeg) = self
+                .data
+                .get(offset as usize..(offset as usize).saturating_add(length as usize))
+            else {
+                continue;
+            };
+            if jpeg.starts_with(&[0xFF, 0xD8]) && best.is_none_or(|b| jpeg.len() > b.len()) {
+                best = Some(jpeg);
+            }
+        }
+        best
+    }
+}
+
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TIFF with one IFD holding `entries` as (tag, kind, count, value),
+    /// values longer than 4 bytes stored after the IFD.
+    fn build(big_endian: bool, entries: &[(u16, u16, u32, Vec<u8>)]) -> Vec<u8> {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+        let mut data = if big_endian { b"MM".to_vec() } else { b"II".to_vec() };
+        data.extend(u16_bytes(42));
+        data.extend(u32_bytes(8));
+        data.extend(u16_bytes(entries.len() as u16));
+        let mut extra_pos = 8 + 2 + entries.len() * 12 + 4;
+        let mut extra: Vec<u8> = Vec::new();
+        for (tag, kind, count, value) in entries {
+            data.extend(u16_bytes(*tag));
+            data.extend(u16_bytes(*kind));
+            data.extend(u32_bytes(*count));
+            if value.len() <= 4 {
+                let mut field = value.clone();
+                field.resize(4, 0);
+                data.extend(field);
+            } else {
+                data.extend(u32_bytes(extra_pos as u32));
+                extra.extend(value);
+                extra_pos += value.len();
+            }
+        }
+        data.extend(u32_bytes(0));
+        data.extend(extra);
+        data
+    }
+
+    fn entries(big_endian: bool) -> Vec<(u16, u16, u32, Vec<u8>)> {
+        let u16s = |values: &[u16]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|v| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() })
+                .collect()
+        };
+        let u32s = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|v| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() })
+                .collect()
+        };
+        vec![
+            (0x0100, 3, 1, u16s(&[6000])),
+            (0x0101, 3, 3, u16s(&[1, 2, 3])),
+            (0x0102, 4, 2, u32s(&[70000, 80000])),
+            (0x010F, 2, 9, b"FUJIFILM\0".to_vec()),
+            (0x829A, 5, 1, u32s(&[1, 250])),
+            (0x9204, 10, 1, u32s(&[-2i32 as u32, 3])),
+        ]
+    }
+
+    fn check(big_endian: bool) {
+        let data = build(big_endian, &entries(big_endian));
+        let tiff = Tiff::parse(&data).unwrap();
+        assert_eq!(tiff.is_big_endian(), big_endian);
+        let ifd = tiff.ifd(tiff.first_ifd()).unwrap();
+        assert_eq!(ifd.entries.len(), 6);
+        assert_eq!(ifd.next, 0);
+
+        assert_eq!(tiff.uint(ifd.get(0x0100).unwrap()), Some(6000));
+        assert_eq!(tiff.uints(ifd.get(0x0101).unwrap()), Some(vec![1, 2, 3]));
+        assert_eq!(tiff.uints(ifd.get(0x0102).unwrap()), Some(vec![70000, 80000]));
+        assert_eq!(tiff.ascii(ifd.get(0x010F).unwrap()).as_deref(), Some("FUJIFILM"));
+        assert_eq!(tiff.rational(ifd.get(0x829A).unwrap()), Some((1, 250)));
+        assert_eq!(tiff.rational(ifd.get(0x9204).unwrap()), Some((-2, 3)));
+    }
+
+    #[test]
+    fn parses_little_endian_ifd() {
+        check(false);
+    }
+
+    #[test]
+    fn parses_big_endian_ifd() {
+        check(true);
+    }
+
+    #[test]
+    fn rejects_values_past_the_end() {
+        let mut data = build(false, &entries(false));
+        data.truncate(data.len() - 4);
+        let tiff = Tiff::parse(&data).unwrap();
+        let ifd = tiff.ifd(tiff.first_ifd()).unwrap();
+        assert_eq!(tiff.uint(ifd.get(0x0100).unwrap()), Some(6000));
+        assert_eq!(tiff.rational(ifd.get(0x9204).unwrap()), None);
+    }
+
+    #[test]
+    fn finds_largest_embedded_jpeg() {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xD9];
+        jpeg.resize(64, 0);
+        let offset = 8 + 2 + 2 * 12 + 4;
+        let mut data = build(
+            false,
+            &[
+                (TAG_JPEG_OFFSET, 4, 1, (offset as u32).to_le_bytes().to_vec()),
+                (TAG_JPEG_LENGTH, 4, 1, 64u32.to_le_bytes().to_vec()),
+            ],
+        );
+        data.extend(&jpeg);
+        let tiff = Tiff::parse(&data).unwrap();
+        assert_eq!(tiff.largest_embedded_jpeg(), Some(&jpeg[..]));
+    }
+}
@@ -1,26 +1,227 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
 use eframe::egui;
+use lru::LruCache;
 use zune_jpeg::JpegDecoder;
 
-const CACHE_SIZE: usize = 5;
+use crate::analysis::Clipping;
+use crate::histogram::DisplayStats;
+use crate::raf::RafFile;
+use crate::{develop, exif, peaking, scanner, tiff};
+
+/// Longest side of the images decoded for display.
+const MAX_DISPLAY_SIZE: u32 = 2000;
+/// Default memory budget for decoded images and textures together.
+const DEFAULT_MEMORY_BUDGET_MB: usize = 1024;
+/// A full-resolution decode may take up to this fraction (1/n) of the
+/// memory budget; larger images are downscaled to fit.
+const FULL_IMAGE_BUDGET_SHARE: usize = 4;
+/// Default number of images either side of the current one decoded ahead.
+const DEFAULT_PRELOAD_RADIUS: usize = 2;
+/// Overrides the memory budget, in megabytes.
+const MEMORY_BUDGET_ENV_VAR: &str = "PHOTO_CULLER_CACHE_MB";
+/// Overrides the preload radius.
+const PRELOAD_RADIUS_ENV_VAR: &str = "PHOTO_CULLER_PRELOAD_RADIUS";
+/// Upper bound for the default decode pool; more threads than this mostly
+/// compete for disk bandwidth.
+const MAX_DEFAULT_WORKERS: usize = 4;
+/// Overrides the number of decode threads.
+const WORKERS_ENV_VAR: &str = "PHOTO_CULLER_DECODE_THREADS";
+/// Longest side of filmstrip thumbnails.
+const THUMBNAIL_SIZE: u32 = 256;
+/// Memory for filmstrip and grid thumbnails, on top of the memory budget so
+/// browsing them never evicts display images.
+const THUMBNAIL_MEMORY_MB: usize = 128;
+/// Longest side of a zoom tile, within what every GPU accepts as a texture.
+pub const MAX_TILE_SIZE: f32 = 4096.0;
+/// Zoom tiles kept at once, one per photo shown side by side.
+const MAX_TILES: usize = 4;
+/// Overlays kept uploaded, clipping and peaking for each photo on screen.
+const MAX_OVERLAYS: usize = 8;
+const CLIPPED_HIGHLIGHT_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 40, 40);
+const CLIPPED_SHADOW_COLOR: egui::Color32 = egui::Color32::from_rgb(40, 90, 255);
+
+/// How a file is turned into pixels for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RenderMode {
+    /// The JPEG itself, or the camera preview embedded in a RAW.
+    Preview,
+    /// The RAW sensor data developed on the CPU.
+    Raw,
+    /// The preview source at full resolution, cut into tiles when zoomed in.
+    /// Kept one at a time and never uploaded as a whole; see
+    /// `ImageCache::get_tile`.
+    Full,
+    /// A small filmstrip thumbnail of the preview source.
+    Thumbnail,
+    /// Edge strength of the preview source at full resolution, reduced to
+    /// display size. Never uploaded as a whole; see
+    /// `ImageCache::get_peaking_overlay`.
+    Peaking,
+}
+
+/// Identifies a cached image. The same file is cached separately for each
+/// render mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ImageKey {
+    pub path: PathBuf,
+    pub mode: RenderMode,
+}
+
+impl ImageKey {
+    pub fn new(path: &Path, mode: RenderMode) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            mode,
+        }
+    }
+}
+
+/// True if `render_raw` can develop this file. Only RAF is supported.
+pub fn supports_raw_rendering(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("raf"))
+}
 
 pub struct DecodedImage {
     pub pixels: Vec<u8>,
     pub width: usize,
     pub height: usize,
+    /// Upright size before downscaling for display.
+    pub source_size: [usize; 2],
+    /// Histogram and clipping, measured for display images only.
+    pub stats: Option<Arc<DisplayStats>>,
+}
+
+impl DecodedImage {
+    fn size_bytes(&self) -> usize {
+        self.pixels.len() + self.stats.as_ref().map_or(0, |stats| stats.size_bytes())
+    }
+}
+
+/// An uploaded display image and the size of the image it was scaled from.
+#[derive(Clone)]
+pub struct DisplayTexture {
+    pub texture: egui::TextureHandle,
+    pub source_size: egui::Vec2,
+    pub stats: Option<Arc<DisplayStats>>,
+}
+
+/// Part of a full-resolution image uploaded for the zoomed view.
+struct Tile {
+    key: ImageKey,
+    /// Covered area in full-resolution pixels.
+    region: egui::Rect,
+    texture: egui::TextureHandle,
+}
+
+/// What an overlay marks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverlayKind {
+    /// Clipped areas of a display image.
+    Clipping,
+    /// Edges of a `RenderMode::Peaking` map at least `threshold` strong.
+    Peaking {
+        threshold: u8,
+        color: egui::Color32,
+    },
+}
+
+/// A texture painted over a display image.
+struct Overlay {
+    key: ImageKey,
+    kind: OverlayKind,
+    texture: egui::TextureHandle,
+}
+
+/// A cached image, either waiting in RAM or already uploaded to the GPU.
+enum CacheEntry {
+    Decoded(DecodedImage),
+    Texture(DisplayTexture),
+}
+
+impl CacheEntry {
+    /// RGBA8 bytes held, in RAM or on the GPU.
+    fn size_bytes(&self) -> usize {
+        match self {
+            CacheEntry::Decoded(decoded) => decoded.size_bytes(),
+            CacheEntry::Texture(display) => {
+                texture_bytes(&display.texture)
+                    + display.stats.as_ref().map_or(0, |stats| stats.size_bytes())
+            }
+        }
+    }
+}
+
+/// RGBA8 bytes of an uploaded texture.
+fn texture_bytes(texture: &egui::TextureHandle) -> usize {
+    let [width, height] = texture.size();
+    width * height * 4
+}
+
+/// A queued decode. Lower priorities are decoded first; `seq` keeps equal
+/// priorities in request order.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct LoadRequest {
+    priority: usize,
+    seq: u64,
+    key: ImageKey,
+}
+
+/// Decode requests waiting for a worker, nearest to the current image first.
+#[derive(Default)]
+struct LoadQueue {
+    pending: BinaryHeap<Reverse<LoadRequest>>,
+    /// Filmstrip thumbnails, decoded only when nothing is pending.
+    thumbnails: VecDeque<ImageKey>,
+    next_seq: u64,
+    /// Bumped by `ImageCache::clear`; results from older generations are stale.
+    generation: u64,
+    /// Largest `RenderMode::Full` decode to keep, in bytes.
+    max_full_bytes: usize,
+    /// Full-resolution decodes already in memory for queued
+    /// `RenderMode::Peaking` keys, used instead of decoding the file again.
+    sources: HashMap<ImageKey, Arc<DecodedImage>>,
+    shutdown: bool,
 }
 
+type SharedQueue = Arc<(Mutex<LoadQueue>, Condvar)>;
+type DecodeResult = (u64, ImageKey, Option<DecodedImage>);
+
 pub struct ImageCache {
-    textures: HashMap<PathBuf, egui::TextureHandle>,
-    decoded: HashMap<PathBuf, DecodedImage>,
-    receiver: Receiver<(PathBuf, DecodedImage)>,
-    sender: Sender<PathBuf>,
-    loading: Vec<PathBuf>,
-    lru_order: Vec<PathBuf>,
+    /// Decoded images and textures in least-recently-used order.
+    entries: LruCache<ImageKey, CacheEntry>,
+    cached_bytes: usize,
+    memory_budget: usize,
+    preload_radius: usize,
+    queue: SharedQueue,
+    receiver: Receiver<DecodeResult>,
+    loading: HashSet<ImageKey>,
+    failed: HashSet<ImageKey>,
+    /// (current index, pair count, current key) of the last preload, to skip
+    /// redundant work.
+    last_preload: Option<(usize, usize, ImageKey)>,
+    /// Images on screen, never evicted however much is preloaded around them.
+    pinned: HashSet<ImageKey>,
+    /// The one `RenderMode::Full` decode kept for cutting zoom tiles, apart
+    /// from the display images so it can't push them out.
+    full: Option<(ImageKey, Arc<DecodedImage>)>,
+    /// Zoomed-in regions currently uploaded, most recently cut last.
+    tiles: Vec<Tile>,
+    /// Overlays currently uploaded, most recently made last.
+    overlays: Vec<Overlay>,
+    /// Filmstrip thumbnails by preview path, kept apart from the display
+    /// images so browsing the strip never evicts them.
+    thumbnails: LruCache<PathBuf, CacheEntry>,
+    thumbnail_bytes: usize,
+    /// Paths passed to the last `request_thumbnails`, to skip redundant work.
+    requested_thumbnails: Vec<PathBuf>,
 }
 
 impl Default for ImageCache {
@@ -29,153 +230,935 @@ impl Default for ImageCache {
     }
 }
 
+impl Drop for ImageCache {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.queue;
+        lock.lock().unwrap().shutdown = true;
+        condvar.notify_all();
+    }
+}
+
+fn env_usize(name: &str) -> Option<usize> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+/// Decode thread count: `PHOTO_CULLER_DECODE_THREADS` if set, otherwise
+/// one per core up to `MAX_DEFAULT_WORKERS`.
+fn default_worker_count() -> usize {
+    env_usize(WORKERS_ENV_VAR)
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map_or(2, |n| n.get())
+                .min(MAX_DEFAULT_WORKERS)
+        })
+}
+
 impl ImageCache {
     pub fn new() -> Self {
-        let (request_sender, request_receiver) = channel::<PathBuf>();
-        let (result_sender, result_receiver) = channel::<(PathBuf, DecodedImage)>();
-
-        thread::spawn(move || {
-            while let Ok(path) = request_receiver.recv() {
-                let file_name = path.display().to_string();
-                println!("Decoding in thread: {}", file_name);
-                if let Some(decoded) = decode_image(&path) {
-                    println!("Decoded: {} ({}x{})", file_name, decoded.width, decoded.height);
-                    let _ = result_sender.send((path, decoded));
-                } else {
-                    eprintln!("Failed to decode: {}", file_name);
-                }
-            }
-        });
+        Self::with_workers(default_worker_count())
+    }
 
-        Self {
-            textures: HashMap::new(),
-            decoded: HashMap::new(),
-            receiver: result_receiver,
-            sender: request_sender,
-            loading: Vec::new(),
-            lru_order: Vec::new(),
+    pub fn with_workers(workers: usize) -> Self {
+        let queue: SharedQueue = Arc::new((Mutex::new(LoadQueue::default()), Condvar::new()));
+        let (result_sender, result_receiver) = channel::<DecodeResult>();
+
+        for _ in 0..workers.max(1) {
+            let queue = Arc::clone(&queue);
+            let result_sender = result_sender.clone();
+            thread::spawn(move || decode_worker(queue, result_sender));
         }
+
+        let memory_budget_mb =
+            env_usize(MEMORY_BUDGET_ENV_VAR).unwrap_or(DEFAULT_MEMORY_BUDGET_MB);
+
+        let mut cache = Self {
+            entries: LruCache::unbounded(),
+            cached_bytes: 0,
+            memory_budget: 0,
+            preload_radius: env_usize(PRELOAD_RADIUS_ENV_VAR).unwrap_or(DEFAULT_PRELOAD_RADIUS),
+            queue,
+            receiver: result_receiver,
+            loading: HashSet::new(),
+            failed: HashSet::new(),
+            last_preload: None,
+            pinned: HashSet::new(),
+            full: None,
+            tiles: Vec::new(),
+            overlays: Vec::new(),
+            thumbnails: LruCache::unbounded(),
+            thumbnail_bytes: 0,
+            requested_thumbnails: Vec::new(),
+        };
+        cache.set_memory_budget_mb(memory_budget_mb);
+        cache
+    }
+
+    pub fn memory_budget_mb(&self) -> usize {
+        self.memory_budget / (1024 * 1024)
+    }
+
+    pub fn set_memory_budget_mb(&mut self, megabytes: usize) {
+        self.memory_budget = megabytes * 1024 * 1024;
+        self.queue.0.lock().unwrap().max_full_bytes = self.memory_budget / FULL_IMAGE_BUDGET_SHARE;
+        self.evict_if_needed();
+    }
+
+    pub fn preload_radius(&self) -> usize {
+        self.preload_radius
+    }
+
+    pub fn set_preload_radius(&mut self, radius: usize) {
+        self.preload_radius = radius;
+        self.last_preload = None;
+    }
+
+    /// Bytes currently held by decoded images and textures, including the
+    /// full-resolution decode, zoom tiles, overlays and thumbnails.
+    pub fn cached_bytes(&self) -> usize {
+        self.cached_bytes + self.thumbnail_bytes
     }
 
     pub fn poll(&mut self) {
-        while let Ok((path, decoded)) = self.receiver.try_recv() {
-            let path_str = path.display().to_string();
+        let generation = self.queue.0.lock().unwrap().generation;
+        while let Ok((result_generation, key, decoded)) = self.receiver.try_recv() {
+            if result_generation != generation {
+                continue;
+            }
+            let path_str = key.path.display().to_string();
             println!("Received image result for path {path_str}");
-            self.loading.retain(|p| p != &path);
-            self.decoded.insert(path, decoded);
+            self.loading.remove(&key);
+            match decoded {
+                Some(decoded) if key.mode == RenderMode::Thumbnail => {
+                    self.insert_thumbnail(key.path, CacheEntry::Decoded(decoded));
+                }
+                Some(decoded) if key.mode == RenderMode::Full => {
+                    self.cached_bytes += decoded.size_bytes();
+                    if let Some((_, old)) = self.full.replace((key, Arc::new(decoded))) {
+                        self.cached_bytes -= old.size_bytes();
+                    }
+                    self.evict_if_needed();
+                }
+                Some(decoded) => self.insert(key, CacheEntry::Decoded(decoded)),
+                None => {
+                    self.failed.insert(key);
+                }
+            }
         }
     }
 
-    pub fn request_load(&mut self, path: &Path) {
-        let path_buf = path.to_path_buf();
+    /// Queues `key` for decoding. Lower `priority` values are decoded first.
+    /// Keys already queued keep their place until `reprioritize` runs. Only
+    /// one full-resolution decode (`RenderMode::Full` or `RenderMode::Peaking`)
+    /// runs at a time, and peaking reuses the zoom decode when it is loaded.
+    pub fn request_load(&mut self, key: &ImageKey, priority: usize) {
+        if self.entries.contains(key)
+            || self.full.as_ref().is_some_and(|(full_key, _)| full_key == key)
+            || self.loading.contains(key)
+            || self.failed.contains(key)
+        {
+            return;
+        }
+        let full_resolution = |mode| matches!(mode, RenderMode::Full | RenderMode::Peaking);
+        if full_resolution(key.mode) && self.loading.iter().any(|k| full_resolution(k.mode)) {
+            return;
+        }
 
-        if self.textures.contains_key(&path_buf)
-            || self.decoded.contains_key(&path_buf)
-            || self.loading.contains(&path_buf)
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        if key.mode == RenderMode::Peaking
+            && let Some((full_key, full)) = &self.full
+            && full_key.path == key.path
         {
+            queue.sources.insert(key.clone(), Arc::clone(full));
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.pending.push(Reverse(LoadRequest {
+            priority,
+            seq,
+            key: key.clone(),
+        }));
+        self.loading.insert(key.clone());
+        condvar.notify_one();
+    }
+
+    /// Replaces the queued thumbnails with those for `paths`, decoded in
+    /// that order once no display image is waiting.
+    pub fn request_thumbnails(&mut self, paths: Vec<PathBuf>) {
+        if paths == self.requested_thumbnails {
             return;
         }
 
-        self.loading.push(path_buf.clone());
-        let _ = self.sender.send(path_buf);
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        for key in queue.thumbnails.drain(..) {
+            self.loading.remove(&key);
+        }
+        for path in &paths {
+            let key = ImageKey::new(path, RenderMode::Thumbnail);
+            if self.thumbnails.contains(path)
+                || self.loading.contains(&key)
+                || self.failed.contains(&key)
+            {
+                continue;
+            }
+            self.loading.insert(key.clone());
+            queue.thumbnails.push_back(key);
+        }
+        condvar.notify_all();
+        drop(queue);
+        self.requested_thumbnails = paths;
     }
 
-    pub fn get_texture(&mut self, ctx: &egui::Context, path: &Path) -> Option<&egui::TextureHandle> {
-        let path_buf = path.to_path_buf();
+    /// The thumbnail for a preview path, uploading it on first use.
+    pub fn get_thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        path: &Path,
+    ) -> Option<&egui::TextureHandle> {
+        if let Some(CacheEntry::Decoded(decoded)) = self.thumbnails.peek(path) {
+            let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                [decoded.width, decoded.height],
+                &decoded.pixels,
+            );
+            let texture = ctx.load_texture(
+                format!("{}#thumbnail", path.display()),
+                color_image,
+                egui::TextureOptions::LINEAR,
+            );
+            let display = DisplayTexture {
+                texture,
+                source_size: egui::vec2(decoded.source_size[0] as f32, decoded.source_size[1] as f32),
+                stats: None,
+            };
+            self.insert_thumbnail(path.to_path_buf(), CacheEntry::Texture(display));
+        }
+
+        match self.thumbnails.get(path) {
+            Some(CacheEntry::Texture(display)) => Some(&display.texture),
+            _ => None,
+        }
+    }
+
+    /// Caches a thumbnail, dropping the least recently used ones beyond
+    /// `THUMBNAIL_MEMORY_MB`.
+    fn insert_thumbnail(&mut self, path: PathBuf, entry: CacheEntry) {
+        self.thumbnail_bytes += entry.size_bytes();
+        if let Some(old) = self.thumbnails.put(path, entry) {
+            self.thumbnail_bytes -= old.size_bytes();
+        }
+        while self.thumbnail_bytes > THUMBNAIL_MEMORY_MB * 1024 * 1024 {
+            let Some((_, evicted)) = self.thumbnails.pop_lru() else {
+                break;
+            };
+            self.thumbnail_bytes -= evicted.size_bytes();
+        }
+    }
+
+    /// Re-ranks queued requests by distance from the current image and drops
+    /// those outside the preload window, so the workers don't decode images
+    /// the user has already moved past.
+    fn reprioritize(&mut self, wanted: &HashMap<&ImageKey, usize>) {
+        let mut queue = self.queue.0.lock().unwrap();
+        let requests = std::mem::take(&mut queue.pending).into_vec();
+        for Reverse(mut request) in requests {
+            match wanted.get(&request.key) {
+                Some(&priority) => {
+                    request.priority = priority;
+                    queue.pending.push(Reverse(request));
+                }
+                None => {
+                    queue.sources.remove(&request.key);
+                    self.loading.remove(&request.key);
+                }
+            }
+        }
+    }
+
+    /// True if the image could not be decoded.
+    pub fn is_failed(&self, key: &ImageKey) -> bool {
+        self.failed.contains(key)
+    }
 
-        if let Some(decoded) = self.decoded.remove(&path_buf) {
-            let file_name = path.display().to_string();
+    /// The display texture for `key`, uploading it on first use. Not for
+    /// `RenderMode::Full` keys, which are shown through `get_tile`, or
+    /// `RenderMode::Peaking` keys.
+    pub fn get_texture(&mut self, ctx: &egui::Context, key: &ImageKey) -> Option<&DisplayTexture> {
+        // Looking the entry up also marks it as most recently used
+        if let Some(CacheEntry::Decoded(_)) = self.entries.get(key) {
+            let Some(CacheEntry::Decoded(decoded)) = self.entries.pop(key) else {
+                unreachable!()
+            };
+            let file_name = key.path.display().to_string();
             println!("Generating texture for: {}", file_name);
-            
+
             let color_image = egui::ColorImage::from_rgba_unmultiplied(
                 [decoded.width, decoded.height],
                 &decoded.pixels,
             );
             let texture = ctx.load_texture(
-                path.to_string_lossy(),
+                format!("{}#{:?}", key.path.display(), key.mode),
                 color_image,
                 egui::TextureOptions::LINEAR,
             );
-            self.textures.insert(path_buf.clone(), texture);
-            self.update_lru(&path_buf);
-            self.evict_if_needed();
+            self.cached_bytes -= decoded.size_bytes();
+            let [source_width, source_height] = decoded.source_size;
+            let display = DisplayTexture {
+                texture,
+                source_size: egui::vec2(source_width as f32, source_height as f32),
+                stats: decoded.stats,
+            };
+            self.insert(key.clone(), CacheEntry::Texture(display));
             println!("Texture generation complete");
         }
 
-        if self.textures.contains_key(&path_buf) {
-            self.update_lru(&path_buf);
-            return self.textures.get(&path_buf);
+        match self.entries.get(key) {
+            Some(CacheEntry::Texture(display)) => Some(display),
+            _ => None,
+        }
+    }
+
+    /// A texture the size of the display texture for `key` with clipped
+    /// highlights and shadows painted in and everything else transparent.
+    /// Only available once the display texture has been uploaded.
+    pub fn get_clipping_overlay(
+        &mut self,
+        ctx: &egui::Context,
+        key: &ImageKey,
+    ) -> Option<egui::TextureHandle> {
+        self.get_overlay(ctx, key, OverlayKind::Clipping)
+    }
+
+    /// A texture with the edges of the decoded `RenderMode::Peaking` map for
+    /// `key` that are at least `threshold` strong painted in `color`, and
+    /// everything else transparent.
+    pub fn get_peaking_overlay(
+        &mut self,
+        ctx: &egui::Context,
+        key: &ImageKey,
+        threshold: u8,
+        color: egui::Color32,
+    ) -> Option<egui::TextureHandle> {
+        self.get_overlay(ctx, key, OverlayKind::Peaking { threshold, color })
+    }
+
+    fn get_overlay(
+        &mut self,
+        ctx: &egui::Context,
+        key: &ImageKey,
+        kind: OverlayKind,
+    ) -> Option<egui::TextureHandle> {
+        if let Some(overlay) = self
+            .overlays
+            .iter()
+            .find(|overlay| overlay.key == *key && overlay.kind == kind)
+        {
+            return Some(overlay.texture.clone());
         }
 
-        None
+        let image = match (kind, self.entries.get(key)?) {
+            (OverlayKind::Clipping, CacheEntry::Texture(display)) => {
+                let stats = display.stats.as_ref()?;
+                let pixels = stats
+                    .clipping
+                    .iter()
+                    .map(|clipping| match clipping {
+                        Clipping::Highlight => CLIPPED_HIGHLIGHT_COLOR,
+                        Clipping::Shadow => CLIPPED_SHADOW_COLOR,
+                        Clipping::None => egui::Color32::TRANSPARENT,
+                    })
+                    .collect();
+                egui::ColorImage {
+                    size: display.texture.size(),
+                    pixels,
+                }
+            }
+            (OverlayKind::Peaking { threshold, color }, CacheEntry::Decoded(edges)) => {
+                let pixels = edges
+                    .pixels
+                    .chunks_exact(4)
+                    .map(|pixel| {
+                        if pixel[3] >= threshold {
+                            color
+                        } else {
+                            egui::Color32::TRANSPARENT
+                        }
+                    })
+                    .collect();
+                egui::ColorImage {
+                    size: [edges.width, edges.height],
+                    pixels,
+                }
+            }
+            _ => return None,
+        };
+        let texture = ctx.load_texture(
+            format!("{}#overlay", key.path.display()),
+            image,
+            egui::TextureOptions::NEAREST,
+        );
+
+        // A changed threshold or colour replaces the old overlay
+        let mut freed = 0;
+        self.overlays.retain(|overlay| {
+            let keep = overlay.key != *key;
+            if !keep {
+                freed += texture_bytes(&overlay.texture);
+            }
+            keep
+        });
+        if self.overlays.len() >= MAX_OVERLAYS {
+            freed += texture_bytes(&self.overlays.remove(0).texture);
+        }
+        self.cached_bytes = self.cached_bytes + texture_bytes(&texture) - freed;
+        self.overlays.push(Overlay {
+            key: key.clone(),
+            kind,
+            texture: texture.clone(),
+        });
+        self.evict_if_needed();
+        Some(texture)
     }
 
-    pub fn preload_adjacent(&mut self, paths: &[PathBuf], current_index: usize) {
-        let indices_to_load: Vec<usize> = [
-            Some(current_index),
-            current_index.checked_add(1).filter(|&i| i < paths.len()),
-            current_index.checked_sub(1),
-            current_index.checked_add(2).filter(|&i| i < paths.len()),
-            current_index.checked_sub(2).filter(|&i| i < paths.len()),
-        ]
-        .into_iter()
-        .flatten()
-        .collect();
+    /// A texture covering `region` (in full-resolution pixels) of a decoded
+    /// `RenderMode::Full` image, and the area it actually covers. The tile
+    /// is cut with a margin around the region and reused until the view
+    /// moves outside it, so the full decode is only needed to cut it.
+    pub fn get_tile(
+        &mut self,
+        ctx: &egui::Context,
+        key: &ImageKey,
+        region: egui::Rect,
+    ) -> Option<(egui::TextureHandle, egui::Rect)> {
+        if let Some(tile) = self.tiles.iter().find(|tile| tile.key == *key)
+            && tile.region.contains_rect(region)
+        {
+            return Some((tile.texture.clone(), tile.region));
+        }
+
+        if region.width() > MAX_TILE_SIZE || region.height() > MAX_TILE_SIZE {
+            return None;
+        }
+        let (_, decoded) = self.full.as_ref().filter(|(full_key, _)| full_key == key)?;
+        // Below 1 when the decode was downscaled to fit the memory budget
+        let scale = decoded.width as f32 / decoded.source_size[0].max(1) as f32;
+        let bounds = egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(decoded.width as f32, decoded.height as f32),
+        );
+        let margin = (egui::Vec2::splat(MAX_TILE_SIZE) - region.size()).max(egui::Vec2::ZERO) / 2.0;
+        let margin = margin.min(region.size() / 2.0);
+        let wanted = region.expand2(margin);
+        let pixel_rect =
+            egui::Rect::from_min_max(wanted.min * scale, wanted.max * scale).intersect(bounds);
+        let pixel_rect = egui::Rect::from_min_max(pixel_rect.min.floor(), pixel_rect.max.ceil());
+        if !pixel_rect.is_positive() {
+            return None;
+        }
+        let tile_rect = egui::Rect::from_min_max(pixel_rect.min / scale, pixel_rect.max / scale);
+
+        let (x0, y0) = (pixel_rect.min.x as usize, pixel_rect.min.y as usize);
+        let (width, height) = (pixel_rect.width() as usize, pixel_rect.height() as usize);
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for row in y0..y0 + height {
+            let start = (row * decoded.width + x0) * 4;
+            pixels.extend_from_slice(&decoded.pixels[start..start + width * 4]);
+        }
+        let color_image = egui::ColorImage::from_rgba_unmultiplied([width, height], &pixels);
+        let texture = ctx.load_texture(
+            format!("{}#tile", key.path.display()),
+            color_image,
+            egui::TextureOptions::LINEAR,
+        );
 
-        for idx in indices_to_load {
-            if let Some(path) = paths.get(idx) {
-                self.request_load(path);
+        let mut freed = 0;
+        self.tiles.retain(|tile| {
+            let keep = tile.key != *key;
+            if !keep {
+                freed += texture_bytes(&tile.texture);
             }
+            keep
+        });
+        if self.tiles.len() >= MAX_TILES {
+            freed += texture_bytes(&self.tiles.remove(0).texture);
         }
+        self.cached_bytes = self.cached_bytes + texture_bytes(&texture) - freed;
+        self.tiles.push(Tile {
+            key: key.clone(),
+            region: tile_rect,
+            texture: texture.clone(),
+        });
+        self.evict_if_needed();
+        Some((texture, tile_rect))
     }
 
-    fn update_lru(&mut self, path: &PathBuf) {
-        self.lru_order.retain(|p| p != path);
-        self.lru_order.push(path.clone());
+    fn insert(&mut self, key: ImageKey, entry: CacheEntry) {
+        self.cached_bytes += entry.size_bytes();
+        if let Some(old) = self.entries.put(key, entry) {
+            self.cached_bytes -= old.size_bytes();
+        }
+        self.evict_if_needed();
     }
 
+    /// Marks the images on screen, which eviction skips so they never have
+    /// to be decoded again while shown.
+    pub fn set_pinned(&mut self, keys: impl IntoIterator<Item = ImageKey>) {
+        self.pinned.clear();
+        self.pinned.extend(keys);
+    }
+
+    /// Drops least-recently-used images that aren't pinned until the cache
+    /// fits its budget, and then the full-resolution decode if it still
+    /// doesn't.
     fn evict_if_needed(&mut self) {
-        while self.textures.len() > CACHE_SIZE && !self.lru_order.is_empty() {
-            let oldest = self.lru_order.remove(0);
-            self.textures.remove(&oldest);
+        while self.cached_bytes > self.memory_budget {
+            let Some(key) = self
+                .entries
+                .iter()
+                .rev()
+                .map(|(key, _)| key)
+                .find(|key| !self.pinned.contains(*key))
+                .cloned()
+            else {
+                break;
+            };
+            if let Some(evicted) = self.entries.pop(&key) {
+                self.cached_bytes -= evicted.size_bytes();
+            }
+        }
+        if self.cached_bytes > self.memory_budget
+            && let Some((_, full)) = self.full.take()
+        {
+            self.cached_bytes -= full.size_bytes();
+        }
+    }
+
+    /// Requests the current image and its neighbours within the preload
+    /// radius, ordered by distance with forward neighbours first.
+    pub fn preload_adjacent(&mut self, keys: &[ImageKey], current_index: usize) {
+        let Some(current) = keys.get(current_index) else {
+            return;
+        };
+        let state = (current_index, keys.len(), current.clone());
+        if self.last_preload.as_ref() == Some(&state) {
+            return;
+        }
+        self.last_preload = Some(state);
+
+        let first = current_index.saturating_sub(self.preload_radius);
+        let last = (current_index + self.preload_radius).min(keys.len() - 1);
+        let wanted: HashMap<&ImageKey, usize> = (first..=last)
+            .filter_map(|idx| {
+                let priority = if idx >= current_index {
+                    (idx - current_index) * 2
+                } else {
+                    (current_index - idx) * 2 + 1
+                };
+                Some((keys.get(idx)?, priority))
+            })
+            .collect();
+
+        self.reprioritize(&wanted);
+        for (key, priority) in wanted {
+            self.request_load(key, priority);
         }
     }
 
     pub fn clear(&mut self) {
-        self.textures.clear();
-        self.decoded.clear();
-        self.lru_order.clear();
+        let mut queue = self.queue.0.lock().unwrap();
+        queue.pending.clear();
+        queue.thumbnails.clear();
+        queue.sources.clear();
+        queue.generation += 1;
+        drop(queue);
+
+        self.last_preload = None;
+        self.entries.clear();
+        self.full = None;
+        self.cached_bytes = 0;
+        self.loading.clear();
+        self.failed.clear();
+        self.tiles.clear();
+        self.overlays.clear();
+        self.thumbnails.clear();
+        self.thumbnail_bytes = 0;
+        self.requested_thumbnails.clear();
     }
 }
 
-fn decode_image(path: &Path) -> Option<DecodedImage> {
+fn decode_worker(queue: SharedQueue, results: Sender<DecodeResult>) {
+    let (lock, condvar) = &*queue;
+    loop {
+        let (generation, max_full_bytes, key, source) = {
+            let mut queue = lock.lock().unwrap();
+            let key = loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(Reverse(request)) = queue.pending.pop() {
+                    break request.key;
+                }
+                if let Some(key) = queue.thumbnails.pop_front() {
+                    break key;
+                }
+                queue = condvar.wait(queue).unwrap();
+            };
+            let source = queue.sources.remove(&key);
+            (queue.generation, queue.max_full_bytes, key, source)
+        };
+
+        let file_name = key.path.display().to_string();
+        println!("Decoding in thread: {} ({:?})", file_name, key.mode);
+        let mut decoded = match key.mode {
+            RenderMode::Preview => decode_image(&key.path, Some(MAX_DISPLAY_SIZE)),
+            RenderMode::Raw => render_raw(&key.path),
+            RenderMode::Full => {
+                decode_image(&key.path, None).map(|full| limit_bytes(full, max_full_bytes))
+            }
+            RenderMode::Thumbnail => decode_thumbnail(&key.path),
+            RenderMode::Peaking => match source {
+                Some(full) => Some(peaking::edge_map(&full, MAX_DISPLAY_SIZE as usize)),
+                None => decode_image(&key.path, None).map(|full| {
+                    let full = limit_bytes(full, max_full_bytes);
+                    peaking::edge_map(&full, MAX_DISPLAY_SIZE as usize)
+                }),
+            },
+        };
+        // Measured here rather than when uploading to keep the UI thread free
+        if let Some(ref mut decoded) = decoded
+            && matches!(key.mode, RenderMode::Preview | RenderMode::Raw)
+        {
+            decoded.stats = Some(Arc::new(DisplayStats::measure(&decoded.pixels)));
+        }
+        match decoded {
+            Some(ref decoded) => {
+                println!("Decoded: {} ({}x{})", file_name, decoded.width, decoded.height);
+            }
+            None => eprintln!("Failed to decode: {}", file_name),
+        }
+        if results.send((generation, key, decoded)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decodes `path` upright, downscaled to `max_size` on the longest side if
+/// one is given.
+pub fn decode_image(path: &Path, max_size: Option<u32>) -> Option<DecodedImage> {
     let ext = path.extension()?.to_str()?.to_lowercase();
 
-    let img = if ext == "jpg" || ext == "jpeg" {
-        // Use zune-jpeg for faster JPEG decoding
+    let (img, orientation) = if ext == "jpg" || ext == "jpeg" {
+        let data = std::fs::read(path).ok()?;
+        (decode_jpeg(&data)?, jpeg_orientation(&data))
+    } else if ext == "raf" {
+        // RAW-only pairs are shown from the camera's embedded preview
+        let raf = RafFile::open(path)?;
+        let info = raf.preview_info()?;
+        println!(
+            "RAF {} (format {}): preview {}x{}",
+            raf.camera_model, raf.format_version, info.width, info.height
+        );
+        (decode_jpeg(&raf.read_preview()?)?, Some(info.orientation))
+    } else if scanner::is_raw_extension(&ext) {
         let data = std::fs::read(path).ok()?;
-        let mut decoder = JpegDecoder::new(&data);
-        let pixels = decoder.decode().ok()?;
-        let info = decoder.info()?;
-
-        let img = image::RgbImage::from_raw(
-            info.width as u32,
-            info.height as u32,
-            pixels,
-        )?;
-        image::DynamicImage::ImageRgb8(img)
+        let jpeg = find_embedded_jpeg(&data)?;
+        // The RAW's own IFD0 is authoritative, previews often lack EXIF
+        let orientation = exif::parse_tiff(&data)
+            .and_then(|e| e.orientation)
+            .or_else(|| jpeg_orientation(jpeg));
+        (decode_jpeg(jpeg)?, orientation)
     } else {
         // Fall back to image crate for other formats
-        image::open(path).ok()?
+        (image::open(path).ok()?, None)
+    };
+
+    Some(prepare_for_display(img, orientation, max_size))
+}
+
+/// Decodes a filmstrip thumbnail, from the small EXIF thumbnail when there
+/// is one since decoding the whole image is much slower.
+fn decode_thumbnail(path: &Path) -> Option<DecodedImage> {
+    if let Some((jpeg, orientation)) = exif::read_thumbnail(path)
+        && let Some(img) = decode_jpeg(&jpeg)
+    {
+        return Some(prepare_for_display(img, orientation, Some(THUMBNAIL_SIZE)));
+    }
+    decode_image(path, Some(THUMBNAIL_SIZE))
+}
+
+/// Develops the RAW sensor data instead of using the camera's rendering, so
+/// it can be compared against the JPEG.
+fn render_raw(path: &Path) -> Option<DecodedImage> {
+    let raf = RafFile::open(path)?;
+    let cfa = raf.read_cfa()?;
+    let orientation = raf.preview_info().map(|info| info.orientation);
+    let img = develop::develop(&cfa)?;
+    Some(prepare_for_display(img, orientation, Some(MAX_DISPLAY_SIZE)))
+}
+
+/// Downscales, turns upright and converts to RGBA for upload.
+fn prepare_for_display(
+    img: image::DynamicImage,
+    orientation: Option<u16>,
+    max_size: Option<u32>,
+) -> DecodedImage {
+    let orientation = orientation.unwrap_or(1);
+    // Orientations 5 to 8 swap width and height
+    let source_size = if orientation >= 5 {
+        [img.height() as usize, img.width() as usize]
+    } else {
+        [img.width() as usize, img.height() as usize]
     };
 
-    // Downscale to max 2000px on longest side for faster display
-    let img = img.thumbnail(2000, 2000);
+    // Downscale for faster display
+    let img = match max_size {
+        Some(max) => img.thumbnail(max, max),
+        None => img,
+    };
+    let img = apply_orientation(img, orientation);
     let rgba = img.to_rgba8();
 
-    Some(DecodedImage {
+    DecodedImage {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        pixels: rgba.into_raw(),
+        source_size,
+        stats: None,
+    }
+}
+
+/// Downscales `decoded` to at most `max_bytes` of pixels, keeping its
+/// `source_size` so regions of the original can still be found in it.
+fn limit_bytes(decoded: DecodedImage, max_bytes: usize) -> DecodedImage {
+    let bytes = decoded.width * decoded.height * 4;
+    if bytes <= max_bytes {
+        return decoded;
+    }
+    let scale = (max_bytes as f64 / bytes as f64).sqrt();
+    let width = ((decoded.width as f64 * scale) as u32).max(1);
+    let height = ((decoded.height as f64 * scale) as u32).max(1);
+    println!(
+        "Downscaling {}x{} to {}x{} to fit the memory budget",
+        decoded.width, decoded.height, width, height
+    );
+    let (full_width, full_height) = (decoded.width as u32, decoded.height as u32);
+    let rgba = image::RgbaImage::from_raw(full_width, full_height, decoded.pixels)
+        .expect("pixel buffer matches the image size");
+    let rgba = image::imageops::thumbnail(&rgba, width, height);
+    DecodedImage {
         width: rgba.width() as usize,
         height: rgba.height() as usize,
         pixels: rgba.into_raw(),
-    })
+        source_size: decoded.source_size,
+        stats: decoded.stats,
+    }
+}
+
+fn jpeg_orientation(data: &[u8]) -> Option<u16> {
+    exif::parse_jpeg(data)?.orientation
+}
+
+/// Turns the stored pixels upright according to the EXIF orientation tag.
+fn apply_orientation(img: image::DynamicImage, orientation: u16) -> image::DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Locates the camera preview inside a non-Fuji RAW file. TIFF-based formats
+/// are walked properly; anything else (e.g. CR3) falls back to scanning for
+/// JPEG start-of-image markers followed by a quantization table or APP
+/// segment and taking the largest complete JPEG, since the first one is
+/// usually a small thumbnail.
+fn find_embedded_jpeg(data: &[u8]) -> Option<&[u8]> {
+    if let Some(jpeg) = tiff::Tiff::parse(data).and_then(|t| t.largest_embedded_jpeg()) {
+        return Some(jpeg);
+    }
+
+    let is_start =
+        |w: &[u8]| w[..3] == [0xFF, 0xD8, 0xFF] && matches!(w[3], 0xDB | 0xE0 | 0xE1);
+    let mut first = None;
+    let mut best: Option<&[u8]> = None;
+    let mut pos = 0;
+    while let Some(offset) = data[pos..].windows(4).position(is_start) {
+        let start = pos + offset;
+        first.get_or_insert(start);
+        match jpeg_length(&data[start..]) {
+            Some(length) => {
+                let jpeg = &data[start..start + length];
+                if best.is_none_or(|b| jpeg.len() > b.len()) {
+                    best = Some(jpeg);
+                }
+                // Thumbnails inside this JPEG's own EXIF are skipped
+                pos = start + length;
+            }
+            None => pos = start + 2,
+        }
+    }
+    // A truncated preview is still better than none
+    best.or_else(|| first.map(|start| &data[start..]))
+}
+
+/// Length of the JPEG at the start of `data`, up to and including its
+/// end-of-image marker, or `None` if it is cut short.
+fn jpeg_length(data: &[u8]) -> Option<usize> {
+    let mut pos = 2;
+    let mut in_scan = false;
+    loop {
+        if in_scan {
+            // Entropy-coded data runs to the next marker that isn't a
+            // stuffed zero, a restart or fill
+            pos += data.get(pos..)?.iter().position(|&b| b == 0xFF)?;
+            match *data.get(pos + 1)? {
+                0x00 | 0xD0..=0xD7 => {
+                    pos += 2;
+                    continue;
+                }
+                0xFF => {
+                    pos += 1;
+                    continue;
+                }
+                _ => in_scan = false,
+            }
+        }
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        match *data.get(pos + 1)? {
+            0xD9 => return Some(pos + 2),
+            0xFF => pos += 1,
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            marker => {
+                let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]);
+                pos += 2 + length as usize;
+                // Progressive JPEGs have several scans with tables between
+                in_scan = marker == 0xDA;
+            }
+        }
+    }
+}
+
+fn decode_jpeg(data: &[u8]) -> Option<image::DynamicImage> {
+    // Use zune-jpeg for faster JPEG decoding
+    let mut decoder = JpegDecoder::new(data);
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+
+    let img = image::RgbImage::from_raw(
+        info.width as u32,
+        info.height as u32,
+        pixels,
+    )?;
+    Some(image::DynamicImage::ImageRgb8(img))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A baseline JPEG skeleton: SOI, a quantization table, a scan holding
+    /// `scan` and EOI.
+    fn jpeg(scan: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x04, 0x00, 0x00];
+        data.extend([0xFF, 0xDA, 0x00, 0x02]);
+        data.extend(scan);
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn measures_jpeg_through_stuffing_and_restarts() {
+        let data = jpeg(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+        assert_eq!(jpeg_length(&data), Some(data.len()));
+        assert_eq!(jpeg_length(&data[..data.len() - 2]), None);
+    }
+
+    #[test]
+    fn fallback_prefers_largest_jpeg() {
+        let thumbnail = jpeg(&[0x01]);
+        let preview = jpeg(&[0x02; 100]);
+        let mut data = b"ftypcrx ".to_vec();
+        data.extend(&thumbnail);
+        data.extend([0u8; 16]);
+        data.extend(&preview);
+        data.extend([0u8; 16]);
+        assert_eq!(find_embedded_jpeg(&data), Some(&preview[..]));
+    }
+
+    fn decoded(width: usize, height: usize) -> DecodedImage {
+        DecodedImage {
+            pixels: vec![0; width * height * 4],
+            width,
+            height,
+            source_size: [width, height],
+            stats: None,
+        }
+    }
+
+    #[test]
+    fn eviction_keeps_pinned_images() {
+        let mut cache = ImageCache::with_workers(1);
+        cache.set_memory_budget_mb(1);
+        let key = |name: &str| ImageKey::new(Path::new(name), RenderMode::Preview);
+        cache.set_pinned([key("current.jpg")]);
+        // Each image takes 640 KB, so only one fits the budget
+        cache.insert(key("current.jpg"), CacheEntry::Decoded(decoded(400, 400)));
+        cache.insert(key("next.jpg"), CacheEntry::Decoded(decoded(400, 400)));
+        cache.insert(key("after.jpg"), CacheEntry::Decoded(decoded(400, 400)));
+        assert!(cache.entries.contains(&key("current.jpg")));
+        assert!(!cache.entries.contains(&key("next.jpg")));
+        assert!(!cache.entries.contains(&key("after.jpg")));
+    }
+
+    #[test]
+    fn downscales_full_decodes_over_the_limit() {
+        let small = limit_bytes(decoded(100, 50), 100 * 50 * 4);
+        assert_eq!((small.width, small.height), (100, 50));
+
+        let large = limit_bytes(decoded(400, 200), 100 * 50 * 4);
+        assert!(large.width * large.height * 4 <= 100 * 50 * 4);
+        assert_eq!(large.width, 2 * large.height);
+        assert_eq!(large.source_size, [400, 200]);
+    }
+
+    #[test]
+    fn loads_one_full_image_at_a_time() {
+        let mut cache = ImageCache::with_workers(1);
+        let full = |name: &str| ImageKey::new(Path::new(name), RenderMode::Full);
+        cache.request_load(&full("a.jpg"), 0);
+        cache.request_load(&full("b.jpg"), 0);
+        assert!(cache.loading.contains(&full("a.jpg")));
+        assert!(!cache.loading.contains(&full("b.jpg")));
+
+        let peaking = ImageKey::new(Path::new("a.jpg"), RenderMode::Peaking);
+        cache.request_load(&peaking, 0);
+        assert!(!cache.loading.contains(&peaking));
+    }
+
+    #[test]
+    fn peaking_reuses_the_zoom_decode() {
+        let mut cache = ImageCache::with_workers(1);
+        // No such file, so only the decode already in memory can be used
+        let path = Path::new("missing.jpg");
+        cache.full = Some((
+            ImageKey::new(path, RenderMode::Full),
+            Arc::new(decoded(64, 32)),
+        ));
+        let peaking = ImageKey::new(path, RenderMode::Peaking);
+        cache.request_load(&peaking, 0);
+        while cache.loading.contains(&peaking) {
+            thread::sleep(std::time::Duration::from_millis(1));
+            cache.poll();
+        }
+        assert!(!cache.is_failed(&peaking));
+        assert!(cache.entries.contains(&peaking));
+    }
 }
@@ -0,0 +1,361 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::photo_pair::{ColorLabel, DeletionAction, MAX_RATING, PhotoPair};
+
+const XMP_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/";
+/// Rating value Lightroom and Capture One use for rejected photos.
+const REJECT_RATING: i32 = -1;
+
+/// The parts of a sidecar the culler reads and writes.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct XmpMetadata {
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+}
+
+/// Sidecar next to the RAW (or the JPEG for JPEG-only pairs), named
+/// `<stem>.xmp` as Lightroom and Capture One expect.
+pub fn sidecar_path(pair: &PhotoPair) -> PathBuf {
+    pair.raw_path
+        .as_deref()
+        .unwrap_or_else(|| pair.display_path())
+        .with_extension("xmp")
+}
+
+/// Applies the rating, label and reject flag from an existing sidecar.
+/// Every edit made in the app is also written to the sidecar, so these
+/// override the session: a sidecar that differs was edited elsewhere.
+pub fn apply_sidecar(pair: &mut PhotoPair) {
+    let path = sidecar_path(pair);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return;
+    };
+    let metadata = parse(&contents);
+    if metadata == XmpMetadata::default() {
+        return;
+    }
+
+    match metadata.rating {
+        Some(REJECT_RATING) => pair.action = DeletionAction::DeleteBoth,
+        Some(rating) => {
+            pair.rating = rating.clamp(0, MAX_RATING as i32) as u8;
+            // Un-rejected in another tool
+            if pair.action == DeletionAction::DeleteBoth {
+                pair.action = pair.default_action();
+            }
+        }
+        None => {}
+    }
+    // The label is removed from the sidecar when cleared, so a missing one
+    // means no label
+    pair.color_label = metadata.label.and_then(|label| {
+        ColorLabel::ALL
+            .into_iter()
+            .find(|l| l.label().eq_ignore_ascii_case(&label))
+    });
+}
+
+/// Writes the pair's state into its sidecar, merging with any metadata
+/// already there. No file is created for a pair with nothing to record.
+pub fn sync_sidecar(pair: &PhotoPair) -> io::Result<()> {
+    let path = sidecar_path(pair);
+    let rejected = pair.action == DeletionAction::DeleteBoth;
+    let metadata = XmpMetadata {
+        rating: Some(if rejected {
+            REJECT_RATING
+        } else {
+            pair.rating as i32
+        }),
+        label: pair.color_label.map(|l| l.label().to_string()),
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(existing) => {
+            if parse(&existing) == metadata {
+                return Ok(());
+            }
+            merge(&existing, &metadata).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "sidecar can't be merged into")
+            })?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if !rejected && pair.rating == 0 && pair.color_label.is_none() {
+                return Ok(());
+            }
+            new_sidecar(&metadata)
+        }
+        Err(e) => return Err(e),
+    };
+
+    write_atomic(&path, &contents)
+}
+
+fn parse(contents: &str) -> XmpMetadata {
+    XmpMetadata {
+        rating: read_property(contents, "Rating").and_then(|v| v.trim().parse().ok()),
+        label: read_property(contents, "Label").filter(|v| !v.is_empty()),
+    }
+}
+
+/// Reads `<prefix>:<name>` in either attribute or element form, where the
+/// prefix is whatever the sidecar binds the XMP namespace to.
+fn read_property(contents: &str, name: &str) -> Option<String> {
+    let qualified = format!("{}:{}", xmp_prefix(contents)?, name);
+    if let Some((_, value)) = find_attribute(contents, &qualified) {
+        return Some(contents[value].to_string());
+    }
+
+    let open = format!("<{}>", qualified);
+    let close = format!("</{}>", qualified);
+    let start = contents.find(&open)? + open.len();
+    let end = start + contents[start..].find(&close)?;
+    Some(contents[start..end].to_string())
+}
+
+/// Replaces or inserts the XMP property `name`, or removes it when `value`
+/// is `None`. Returns `None` if the sidecar can't be edited safely.
+fn set_property(contents: &str, name: &str, value: Option<&str>) -> Option<String> {
+    let prefix = xmp_prefix(contents);
+    if let Some(prefix) = prefix {
+        let qualified = format!("{}:{}", prefix, name);
+        if let Some((start, range)) = find_attribute(contents, &qualified) {
+            return Some(match value {
+                // The quotes are kept, whichever kind they are
+                Some(v) => format!(
+                    "{}{}{}",
+                    &contents[..range.start],
+                    v,
+                    &contents[range.end..]
+                ),
+                None => {
+                    // Drop the attribute together with the whitespace before it
+                    let trimmed = contents[..start].trim_end();
+                    format!("{}{}", trimmed, &contents[range.end + 1..])
+                }
+            });
+        }
+
+        let open = format!("<{}>", qualified);
+        let close = format!("</{}>", qualified);
+        if let Some(start) = contents.find(&open) {
+            let end = start + contents[start..].find(&close)? + close.len();
+            return Some(match value {
+                Some(v) => format!(
+                    "{}{}{}{}{}",
+                    &contents[..start],
+                    open,
+                    v,
+                    close,
+                    &contents[end..]
+                ),
+                None => format!("{}{}", contents[..start].trim_end(), &contents[end..]),
+            });
+        }
+    }
+
+    let Some(value) = value else {
+        return Some(contents.to_string());
+    };
+    let tag = "<rdf:Description";
+    let insert_at = contents.find(tag)? + tag.len();
+    let mut inserted = String::new();
+    let prefix = match prefix {
+        Some(prefix) => prefix,
+        // `xmp` taken by another namespace; adding ours would clash
+        None if find_attribute(contents, "xmlns:xmp").is_some() => return None,
+        None => {
+            inserted.push_str(&format!("\n    xmlns:xmp=\"{}\"", XMP_NAMESPACE));
+            "xmp"
+        }
+    };
+    inserted.push_str(&format!("\n    {}:{}=\"{}\"", prefix, name, value));
+    Some(format!(
+        "{}{}{}",
+        &contents[..insert_at],
+        inserted,
+        &contents[insert_at..]
+    ))
+}
+
+/// Prefix the sidecar binds the XMP namespace to, usually `xmp`.
+fn xmp_prefix(contents: &str) -> Option<&str> {
+    contents
+        .match_indices("xmlns:")
+        .find_map(|(start, declaration)| {
+            let name_start = start + declaration.len();
+            let name_len = contents[name_start..].find(|c: char| c == '=' || c.is_whitespace())?;
+            let prefix = &contents[name_start..name_start + name_len];
+            let (_, value) = find_attribute(contents, &format!("xmlns:{}", prefix))?;
+            (&contents[value] == XMP_NAMESPACE).then_some(prefix)
+        })
+}
+
+/// Finds the attribute `name` with its value in single or double quotes and
+/// optional whitespace around the `=`. Returns where the attribute starts
+/// and where its value is, excluding the quotes.
+fn find_attribute(contents: &str, name: &str) -> Option<(usize, Range<usize>)> {
+    for (start, _) in contents.match_indices(name) {
+        // Only whole names, so `xmp:Label` doesn't match `myxmp:Label`
+        if !contents[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = contents[start + name.len()..].trim_start();
+        let Some(rest) = rest.strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+        let Some(quote) = rest.chars().next().filter(|&c| c == '"' || c == '\'') else {
+            continue;
+        };
+        let value_start = contents.len() - rest.len() + 1;
+        let value_end = value_start + contents[value_start..].find(quote)?;
+        return Some((start, value_start..value_end));
+    }
+    None
+}
+
+fn merge(existing: &str, metadata: &XmpMetadata) -> Option<String> {
+    let rating = metadata.rating.map(|r| r.to_string());
+    let merged = set_property(existing, "Rating", rating.as_deref())?;
+    set_property(&merged, "Label", metadata.label.as_deref())
+}
+
+fn new_sidecar(metadata: &XmpMetadata) -> String {
+    let mut properties = String::new();
+    if let Some(rating) = metadata.rating {
+        properties.push_str(&format!("\n    xmp:Rating=\"{}\"", rating));
+    }
+    if let Some(ref label) = metadata.label {
+        properties.push_str(&format!("\n    xmp:Label=\"{}\"", label));
+    }
+    format!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\" x:xmptk=\"Photo Culler\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\"\n    xmlns:xmp=\"{}\"{}/>\n \
+         </rdf:RDF>\n</x:xmpmeta>\n",
+        XMP_NAMESPACE, properties
+    )
+}
+
+fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sidecar(description: &str) -> String {
+        format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+             {}\n </rdf:RDF>\n</x:xmpmeta>\n",
+            description
+        )
+    }
+
+    fn metadata(rating: i32, label: Option<&str>) -> XmpMetadata {
+        XmpMetadata {
+            rating: Some(rating),
+            label: label.map(str::to_string),
+        }
+    }
+
+    /// Merges `metadata` into `existing` and checks it reads back and each
+    /// property is there once.
+    fn assert_merges(existing: &str, metadata: &XmpMetadata) -> String {
+        let merged = merge(existing, metadata).unwrap();
+        assert_eq!(parse(&merged), *metadata, "{}", merged);
+        assert_eq!(occurrences(&merged, "Rating"), 1, "{}", merged);
+        assert!(occurrences(&merged, "Label") <= 1, "{}", merged);
+        merged
+    }
+
+    /// Times `name` is set, as an attribute or as an element.
+    fn occurrences(contents: &str, name: &str) -> usize {
+        contents
+            .match_indices(&format!(":{}", name))
+            // Skip closing tags
+            .filter(|&(i, _)| {
+                !contents[..i]
+                    .rsplit('<')
+                    .next()
+                    .unwrap_or("")
+                    .starts_with('/')
+            })
+            .count()
+    }
+
+    #[test]
+    fn merges_double_quoted_attributes() {
+        let existing = sidecar(&format!(
+            "<rdf:Description rdf:about=\"\" xmlns:xmp=\"{}\" xmp:Rating=\"2\" xmp:Label=\"Red\"/>",
+            XMP_NAMESPACE
+        ));
+        assert_eq!(parse(&existing), metadata(2, Some("Red")));
+        assert_merges(&existing, &metadata(4, Some("Blue")));
+        let merged = assert_merges(&existing, &metadata(4, None));
+        assert!(merged.contains("xmp:Rating=\"4\"/>"));
+    }
+
+    #[test]
+    fn merges_single_quoted_attributes() {
+        let existing = sidecar(&format!(
+            "<rdf:Description rdf:about='' xmlns:xmp='{}' xmp:Rating = '2'/>",
+            XMP_NAMESPACE
+        ));
+        assert_eq!(parse(&existing), metadata(2, None));
+        let merged = assert_merges(&existing, &metadata(5, Some("Green")));
+        assert!(merged.contains("xmp:Rating = '5'"));
+    }
+
+    #[test]
+    fn merges_namespace_bound_to_another_prefix() {
+        let existing = sidecar(&format!(
+            "<rdf:Description rdf:about=\"\" xmlns:xap=\"{}\" xap:Rating=\"1\"/>",
+            XMP_NAMESPACE
+        ));
+        assert_eq!(parse(&existing), metadata(1, None));
+        let merged = assert_merges(&existing, &metadata(3, Some("Yellow")));
+        assert!(merged.contains("xap:Rating=\"3\""));
+        assert!(merged.contains("xap:Label=\"Yellow\""));
+        assert!(!merged.contains("xmlns:xmp"));
+    }
+
+    #[test]
+    fn merges_elements() {
+        let existing = sidecar(&format!(
+            "<rdf:Description rdf:about=\"\" xmlns:xmp=\"{}\">\n   \
+             <xmp:Rating>2</xmp:Rating>\n   <xmp:Label>Red</xmp:Label>\n  </rdf:Description>",
+            XMP_NAMESPACE
+        ));
+        assert_eq!(parse(&existing), metadata(2, Some("Red")));
+        let merged = assert_merges(&existing, &metadata(-1, Some("Purple")));
+        assert!(merged.contains("<xmp:Rating>-1</xmp:Rating>"));
+        assert_merges(&existing, &metadata(0, None));
+    }
+
+    #[test]
+    fn adds_the_namespace_when_missing() {
+        let existing = sidecar(
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"/>",
+        );
+        assert_eq!(parse(&existing), XmpMetadata::default());
+        let merged = assert_merges(&existing, &metadata(3, None));
+        assert!(merged.contains(&format!("xmlns:xmp=\"{}\"", XMP_NAMESPACE)));
+    }
+
+    #[test]
+    fn refuses_a_clashing_prefix() {
+        let existing =
+            sidecar("<rdf:Description rdf:about=\"\" xmlns:xmp=\"urn:other\" xmp:Rating=\"2\"/>");
+        assert_eq!(parse(&existing), XmpMetadata::default());
+        assert_eq!(merge(&existing, &metadata(3, None)), None);
+    }
+}
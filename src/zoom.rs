@@ -0,0 +1,141 @@
+//! Zoom and pan geometry for the central image panel.
+
+use eframe::egui::{self, Pos2, Rect, Vec2, vec2};
+
+/// Deepest zoom, in screen pixels per image pixel.
+pub const MAX_ZOOM: f32 = 8.0;
+
+/// A zoomed-in view. Both fields are independent of the photo's size, so the
+/// view can carry over to the next photo when the zoom is locked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomView {
+    /// Screen pixels per image pixel; 1.0 is 100%.
+    pub zoom: f32,
+    /// Point of the upright image shown at the panel centre, as a fraction
+    /// of its width and height.
+    pub center: Vec2,
+}
+
+/// Where a photo lands in the panel. Image positions are in pixels of the
+/// full-resolution upright image, before the manual rotation, which turns
+/// the image about the panel centre.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLayout {
+    pub panel: Rect,
+    pub image_size: Vec2,
+    /// Manual rotation in clockwise quarter turns.
+    pub rotation: u8,
+    /// Screen points per image pixel.
+    pub scale: f32,
+    /// Image position shown at the panel centre.
+    pub center: Pos2,
+}
+
+impl ImageLayout {
+    /// The whole image centred in the panel, shown no larger than
+    /// `max_scale` points per image pixel.
+    pub fn fit(panel: Rect, image_size: Vec2, rotation: u8, max_scale: f32) -> Self {
+        let rotated_size = if rotation % 2 == 1 {
+            vec2(image_size.y, image_size.x)
+        } else {
+            image_size
+        };
+        let scale = (panel.width() / rotated_size.x)
+            .min(panel.height() / rotated_size.y)
+            .min(max_scale);
+        Self {
+            panel,
+            image_size,
+            rotation,
+            scale,
+            center: (image_size / 2.0).to_pos2(),
+        }
+    }
+
+    pub fn zoomed(
+        panel: Rect,
+        image_size: Vec2,
+        rotation: u8,
+        view: ZoomView,
+        pixels_per_point: f32,
+    ) -> Self {
+        Self {
+            panel,
+            image_size,
+            rotation,
+            scale: view.zoom / pixels_per_point,
+            center: (view.center.clamp(Vec2::ZERO, Vec2::splat(1.0)) * image_size).to_pos2(),
+        }
+    }
+
+    /// Screen pixels per image pixel.
+    pub fn zoom(&self, pixels_per_point: f32) -> f32 {
+        self.scale * pixels_per_point
+    }
+
+    pub fn angle(&self) -> f32 {
+        self.rotation as f32 * std::f32::consts::FRAC_PI_2
+    }
+
+    /// Undoes the manual rotation of a screen-space vector.
+    fn unrotate(&self, v: Vec2) -> Vec2 {
+        match self.rotation % 4 {
+            1 => vec2(v.y, -v.x),
+            2 => -v,
+            3 => vec2(-v.y, v.x),
+            _ => v,
+        }
+    }
+
+    /// Image position under a screen position.
+    pub fn image_pos(&self, screen: Pos2) -> Pos2 {
+        self.center + self.unrotate(screen - self.panel.center()) / self.scale
+    }
+
+    /// Part of the image inside the panel, in image pixels.
+    pub fn visible_region(&self) -> Rect {
+        let panel = self.panel;
+        let corners = [
+            panel.left_top(),
+            panel.right_top(),
+            panel.left_bottom(),
+            panel.right_bottom(),
+        ]
+        .map(|corner| self.image_pos(corner));
+        Rect::from_points(&corners).intersect(Rect::from_min_size(Pos2::ZERO, self.image_size))
+    }
+
+    /// Paints `texture` stretched over `region` of the image.
+    pub fn paint(&self, ui: &egui::Ui, texture: egui::TextureId, region: Rect) {
+        // Lay the region out unrotated around the panel centre, then turn it
+        // about that centre
+        let unrotated = |p: Pos2| self.panel.center() + (p - self.center) * self.scale;
+        let rect = Rect::from_min_max(unrotated(region.min), unrotated(region.max));
+        let origin = (self.panel.center() - rect.min) / rect.size();
+        egui::Image::new((texture, rect.size()))
+            .rotate(self.angle(), origin)
+            .paint_at(ui, rect);
+    }
+
+    /// The view after zooming to `zoom` while keeping the image point under
+    /// `screen` in place.
+    pub fn zoom_about(&self, screen: Pos2, zoom: f32, pixels_per_point: f32) -> ZoomView {
+        let anchor = self.image_pos(screen);
+        let scale = zoom / pixels_per_point;
+        let center = anchor - self.unrotate(screen - self.panel.center()) / scale;
+        self.view(center, zoom)
+    }
+
+    /// The view after dragging the image by `delta` screen points.
+    pub fn panned(&self, delta: Vec2, pixels_per_point: f32) -> ZoomView {
+        let center = self.center - self.unrotate(delta) / self.scale;
+        self.view(center, self.zoom(pixels_per_point))
+    }
+
+    fn view(&self, center: Pos2, zoom: f32) -> ZoomView {
+        ZoomView {
+            zoom,
+            center: (center.to_vec2() / self.image_size).clamp(Vec2::ZERO, Vec2::splat(1.0)),
+        }
+    }
+}